@@ -0,0 +1,73 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct Record {
+    id: u64,
+    name: String,
+    description: String,
+    score: f64,
+    offset: i64,
+    enabled: bool,
+    tags: Vec<String>,
+    attributes: BTreeMap<String, u32>
+}
+
+// Records with a mix of plain and escaped strings, integers and floats.
+fn records(count: usize) -> Vec<Record> {
+    (0..count).map(|idx| {
+        let idx64 = idx as u64;
+        Record {
+            id: idx64 * 7919,
+            name: format!("record_{}", idx),
+            description: if idx % 4 == 0 {
+                format!("line one\nline \"two\" with a [bracket] and a \\ for record {}", idx)
+            } else {
+                format!("An ordinary description of record number {}, with nothing to escape.", idx)
+            },
+            score: idx as f64 * 1.618_033_988_75,
+            offset: -(idx as i64) * 104_729,
+            enabled: idx % 3 == 0,
+            tags: (0..idx % 5).map(|tag| format!("tag-{}", tag)).collect(),
+            attributes: (0..4).map(|key| (format!("attr{}", key), key * 1000 + idx as u32)).collect()
+        }
+    }).collect()
+}
+
+fn serialize(c: &mut Criterion) {
+    let data = records(1000);
+    let mut group = c.benchmark_group("serialize");
+    group.throughput(Throughput::Elements(data.len() as u64));
+
+    group.bench_function("lson to_string", |b| {
+        b.iter(|| serde_lson::ser::to_string(&data).unwrap())
+    });
+    group.bench_function("json to_string", |b| {
+        b.iter(|| serde_json::to_string(&data).unwrap())
+    });
+    group.bench_function("lson to_string_pretty", |b| {
+        b.iter(|| serde_lson::ser::to_string_pretty(&data).unwrap())
+    });
+    group.bench_function("json to_string_pretty", |b| {
+        b.iter(|| serde_json::to_string_pretty(&data).unwrap())
+    });
+
+    let mut output = Vec::new();
+    group.bench_function("lson to_writer", |b| {
+        b.iter(|| {
+            output.clear();
+            serde_lson::ser::to_writer(&data, &mut output).unwrap();
+        })
+    });
+    group.bench_function("json to_writer", |b| {
+        b.iter(|| {
+            output.clear();
+            serde_json::to_writer(&mut output, &data).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, serialize);
+criterion_main!(benches);
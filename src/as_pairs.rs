@@ -0,0 +1,255 @@
+
+// Reads and writes maps as lists of pairs, `{ { key, value }, ... }`, so
+// that their order survives in Lua, where `pairs` iterates tables in no
+// particular order. For use with `#[serde(with = "serde_lson::as_pairs")]`
+// on an `IndexMap` or other ordered map, or on a `Vec<(K, V)>`;
+// `as_pairs::named` writes the pairs as `{ k = key, v = value }` instead.
+//
+// Reading accepts either form of pair.
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeTuple, Serializer};
+
+// An item of what `serialize` writes: `(K, V)`, which includes the
+// `(&K, &V)` of a map, or the `&(K, V)` of a list of pairs.
+pub trait AsPair {
+    type Key: Serialize;
+    type Value: Serialize;
+
+    fn as_pair(&self) -> (&Self::Key, &Self::Value);
+}
+
+impl<K: Serialize, V: Serialize> AsPair for (K, V) {
+    type Key = K;
+    type Value = V;
+
+    fn as_pair(&self) -> (&K, &V) {
+        (&self.0, &self.1)
+    }
+}
+
+impl<K: Serialize, V: Serialize> AsPair for &(K, V) {
+    type Key = K;
+    type Value = V;
+
+    fn as_pair(&self) -> (&K, &V) {
+        (&self.0, &self.1)
+    }
+}
+
+pub fn serialize<T, S>(map: T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: IntoIterator,
+    T::Item: AsPair,
+    S: Serializer
+{
+    serializer.collect_seq(map.into_iter().map(ListPair))
+}
+
+pub fn deserialize<'de, T, K, V, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: IntoIterator<Item = (K, V)> + FromIterator<(K, V)>,
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+    D: Deserializer<'de>
+{
+    deserializer.deserialize_seq(PairsVisitor { output: PhantomData })
+}
+
+pub mod named {
+    use serde::ser::Serializer;
+
+    use super::{AsPair, NamedPair};
+
+    pub use super::deserialize;
+
+    pub fn serialize<T, S>(map: T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: IntoIterator,
+        T::Item: AsPair,
+        S: Serializer
+    {
+        serializer.collect_seq(map.into_iter().map(NamedPair))
+    }
+}
+
+// `{ key, value }`
+struct ListPair<P>(P);
+
+impl<P: AsPair> Serialize for ListPair<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (key, value) = self.0.as_pair();
+        let mut pair = serializer.serialize_tuple(2)?;
+        pair.serialize_element(key)?;
+        pair.serialize_element(value)?;
+        pair.end()
+    }
+}
+
+// `{ k = key, v = value }`
+struct NamedPair<P>(P);
+
+impl<P: AsPair> Serialize for NamedPair<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (key, value) = self.0.as_pair();
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("k", key)?;
+        map.serialize_entry("v", value)?;
+        map.end()
+    }
+}
+
+struct PairsVisitor<T> {
+    output: PhantomData<T>
+}
+
+impl<'de, T, K, V> Visitor<'de> for PairsVisitor<T>
+where
+    T: IntoIterator<Item = (K, V)> + FromIterator<(K, V)>,
+    K: Deserialize<'de>,
+    V: Deserialize<'de>
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of pairs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let mut pairs = Vec::new();
+        while let Some(Pair(key, value)) = seq.next_element()? {
+            pairs.push((key, value));
+        }
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+// A `{ key, value }` or `{ k = key, v = value }` pair.
+struct Pair<K, V>(K, V);
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Pair<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Pair<K, V>, D::Error> {
+        deserializer.deserialize_any(PairVisitor { output: PhantomData })
+    }
+}
+
+struct PairVisitor<K, V> {
+    output: PhantomData<(K, V)>
+}
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for PairVisitor<K, V> {
+    type Value = Pair<K, V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a `{ key, value }` or `{ k = key, v = value }` pair")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Pair<K, V>, A::Error> {
+        let key = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let value = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        if seq.next_element::<IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(3, &self));
+        }
+        Ok(Pair(key, value))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Pair<K, V>, A::Error> {
+        let mut key = None;
+        let mut value = None;
+        while let Some(name) = map.next_key::<String>()? {
+            match name.as_str() {
+                "k" if key.is_some() => return Err(de::Error::duplicate_field("k")),
+                "v" if value.is_some() => return Err(de::Error::duplicate_field("v")),
+                "k" => key = Some(map.next_value()?),
+                "v" => value = Some(map.next_value()?),
+                _ => return Err(de::Error::unknown_field(&name, &["k", "v"]))
+            }
+        }
+        let key = key.ok_or_else(|| de::Error::missing_field("k"))?;
+        let value = value.ok_or_else(|| de::Error::missing_field("v"))?;
+        Ok(Pair(key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{de, ser};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Steps {
+        #[serde(with = "crate::as_pairs")]
+        steps: Vec<(String, u32)>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Named {
+        #[serde(with = "crate::as_pairs::named")]
+        steps: Vec<(String, u32)>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Sorted {
+        #[serde(with = "crate::as_pairs")]
+        steps: BTreeMap<u32, String>
+    }
+
+    fn steps() -> Vec<(String, u32)> {
+        vec![("b".to_string(), 2), ("a".to_string(), 1)]
+    }
+
+    #[test]
+    fn lists_of_pairs() {
+        let value = Steps { steps: steps() };
+        let text = ser::to_string(&value).unwrap();
+        assert_eq!(text, "{ steps = { { \"b\", 2 }, { \"a\", 1 } } }");
+        assert_eq!(de::from_str::<Steps>(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn named_pairs() {
+        let value = Named { steps: steps() };
+        let text = ser::to_string(&value).unwrap();
+        assert_eq!(text, "{ steps = { { k = \"b\", v = 2 }, { k = \"a\", v = 1 } } }");
+        assert_eq!(de::from_str::<Named>(&text).unwrap(), value);
+        assert_eq!(de::from_str::<Steps>(&text).unwrap().steps, steps());
+    }
+
+    #[test]
+    fn maps() {
+        let value = Sorted { steps: vec![(2, "b".to_string()), (1, "a".to_string())].into_iter().collect() };
+        let text = ser::to_string(&value).unwrap();
+        assert_eq!(text, "{ steps = { { 1, \"a\" }, { 2, \"b\" } } }");
+        assert_eq!(de::from_str::<Sorted>(&text).unwrap(), value);
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn ordered_maps() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Ordered {
+            #[serde(with = "crate::as_pairs")]
+            steps: indexmap::IndexMap<String, u32>
+        }
+
+        let value = Ordered { steps: steps().into_iter().collect() };
+        let text = ser::to_string(&value).unwrap();
+        assert_eq!(text, "{ steps = { { \"b\", 2 }, { \"a\", 1 } } }");
+        assert_eq!(de::from_str::<Ordered>(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn invalid_pairs() {
+        assert!(de::from_str::<Steps>("{ steps = { { \"a\" } } }").is_err());
+        assert!(de::from_str::<Steps>("{ steps = { { \"a\", 1, 2 } } }").is_err());
+        assert!(de::from_str::<Steps>("{ steps = { { k = \"a\" } } }").is_err());
+        assert!(de::from_str::<Steps>("{ steps = { { k = \"a\", v = 1, x = 2 } } }").is_err());
+    }
+}
@@ -0,0 +1,137 @@
+
+// Reads and writes sets as Lua sets, `{ apple = true, ["x y"] = true }`,
+// for use with `#[serde(with = "serde_lson::as_set")]`.
+//
+// Reading also accepts a plain list of members, `{ "apple", "x y" }`. A
+// member of the list form that is itself `true` would be taken for the map
+// form, so sets of booleans should be written as maps.
+
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::value::Value;
+
+pub fn serialize<T, S>(set: T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: IntoIterator,
+    T::Item: Serialize,
+    S: Serializer
+{
+    serializer.collect_map(set.into_iter().map(|member| (member, true)))
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: IntoIterator + FromIterator<<T as IntoIterator>::Item>,
+    T::Item: Deserialize<'de>,
+    D: Deserializer<'de>
+{
+    deserializer.deserialize_map(SetVisitor { output: PhantomData })
+}
+
+struct SetVisitor<T> {
+    output: PhantomData<T>
+}
+
+impl<'de, T> Visitor<'de> for SetVisitor<T>
+where
+    T: IntoIterator + FromIterator<<T as IntoIterator>::Item>,
+    T::Item: Deserialize<'de>
+{
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a set as a table of `member = true` or a list of members")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let mut members = Vec::new();
+        while let Some(member) = seq.next_element()? {
+            members.push(member);
+        }
+        Ok(members.into_iter().collect())
+    }
+
+    // Entries of the list form come through as `[index] = member`, in order.
+    // Any other entry must be `member = true`.
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+        let mut members = Vec::new();
+        let mut position = 1;
+        while let Some((key, value)) = map.next_entry::<Value, Value>()? {
+            let member = match (key, value) {
+                (key, Value::Boolean(true)) => key,
+                (_, Value::Boolean(false)) => {
+                    return Err(de::Error::invalid_value(de::Unexpected::Bool(false), &"`true` for a set member"));
+                },
+                (Value::Integer(index), value) if index == position => {
+                    position += 1;
+                    value
+                },
+                (_, value) => {
+                    return Err(de::Error::invalid_type(de::Unexpected::Other(value.type_name()), &"`true` for a set member"));
+                }
+            };
+            members.push(T::Item::deserialize(member).map_err(de::Error::custom)?);
+        }
+        Ok(members.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{de, ser};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Tags {
+        #[serde(with = "crate::as_set")]
+        tags: BTreeSet<String>
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Ids {
+        #[serde(with = "crate::as_set")]
+        ids: HashSet<u32>
+    }
+
+    fn tags(members: &[&str]) -> Tags {
+        Tags { tags: members.iter().map(|member| member.to_string()).collect() }
+    }
+
+    #[test]
+    fn write_sets() {
+        let text = ser::to_string(&tags(&["apple", "x y"])).unwrap();
+        assert_eq!(text, "{ tags = { apple = true, [\"x y\"] = true } }");
+        assert_eq!(de::from_str::<Tags>(&text).unwrap(), tags(&["apple", "x y"]));
+        let ids = Ids { ids: vec![3, 10].into_iter().collect() };
+        let text = ser::to_string_canonical(&ids).unwrap();
+        assert_eq!(text, "{ ids = { [3] = true, [10] = true } }");
+        assert_eq!(de::from_str::<Ids>(&text).unwrap(), ids);
+    }
+
+    #[test]
+    fn read_lists() {
+        assert_eq!(de::from_str::<Tags>("{ tags = { \"apple\", \"x y\" } }").unwrap(), tags(&["apple", "x y"]));
+        let ids = de::from_str::<Ids>("{ ids = { 10, 3, 10 } }").unwrap();
+        assert_eq!(ids.ids, vec![3, 10].into_iter().collect());
+        assert_eq!(de::from_str::<Tags>("{ tags = {} }").unwrap(), tags(&[]));
+    }
+
+    #[test]
+    fn invalid_sets() {
+        assert!(de::from_str::<Tags>("{ tags = { apple = false } }").is_err());
+        assert!(de::from_str::<Tags>("{ tags = { 1 } }").is_err());
+        assert!(de::from_str::<Tags>("{ tags = \"apple\" }").is_err());
+        let error = de::from_str::<Tags>("{ tags = { apple = \"yes\", pear = \"no\" } }").unwrap_err();
+        assert!(error.to_string().contains("invalid type: string, expected `true` for a set member"), "{}", error);
+        assert!(de::from_str::<Tags>("{ tags = { [2] = \"b\", [1] = \"a\" } }").is_err());
+        assert!(de::from_str::<Tags>("{ tags = { \"a\", [3] = \"c\" } }").is_err());
+    }
+}
@@ -0,0 +1,557 @@
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::str;
+
+use logos::Logos;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task;
+
+use crate::de::{self, Deserializer, DuplicateKeys, Limits};
+use crate::error::{Error, Position, Result, Warning};
+use crate::loader::Loader;
+use crate::parser::{Token, LOOKAHEAD};
+use crate::ser;
+use crate::symbols::Symbols;
+use crate::value::Value;
+
+// How many bytes are read at a time.
+const READ_SIZE: usize = 64 * 1024;
+
+// How many bytes are written at a time.
+const WRITE_SIZE: usize = 8 * 1024;
+
+// How many reads may wait for the parser in `from_async_reader`.
+const READ_AHEAD: usize = 4;
+
+// How many chunks may wait for the writer in `to_async_writer`.
+const WRITE_AHEAD: usize = 4;
+
+// The value is serialized on Tokio's blocking threads, so it is taken by
+// value; the output is written as it's produced, in chunks, and
+// serialization waits while the writer is behind. Must be called from
+// within a Tokio runtime.
+pub async fn to_async_writer<T, W>(value: T, writer: &mut W) -> Result<()>
+where
+    T: Serialize + Send + 'static,
+    W: AsyncWrite + Unpin
+{
+    write_chunked(writer, move |output| ser::to_writer(&value, output)).await
+}
+
+pub async fn to_async_writer_pretty<T, W>(value: T, writer: &mut W) -> Result<()>
+where
+    T: Serialize + Send + 'static,
+    W: AsyncWrite + Unpin
+{
+    write_chunked(writer, move |output| ser::to_writer_pretty(&value, output)).await
+}
+
+// Runs `serialize` on a blocking thread, writing its output to `writer` a
+// chunk at a time. At most `WRITE_AHEAD` chunks wait for the writer.
+async fn write_chunked<W, F>(writer: &mut W, serialize: F) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+    F: FnOnce(&mut ChannelWriter) -> Result<()> + Send + 'static
+{
+    let (sender, mut receiver) = mpsc::channel(WRITE_AHEAD);
+    let serializer = task::spawn_blocking(move || {
+        let mut output = ChannelWriter { sender, chunk: Vec::with_capacity(WRITE_SIZE) };
+        serialize(&mut output)?;
+        output.send().map_err(Error::from)
+    });
+    let mut written = Ok(());
+    while let Some(chunk) = receiver.recv().await {
+        written = writer.write_all(&chunk).await;
+        if written.is_err() {
+            break;
+        }
+    }
+    // A serializer still running fails its next send.
+    drop(receiver);
+    let serialized = join(serializer.await);
+    // The writer's error is what made the serializer fail, if it did.
+    written?;
+    serialized?;
+    writer.flush().await?;
+    Ok(())
+}
+
+// The output of `write_chunked`'s serializer, sent to the writer in chunks
+// of about `WRITE_SIZE` bytes.
+struct ChannelWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+    chunk: Vec<u8>
+}
+
+impl ChannelWriter {
+    // Sends the chunk so far, waiting while the channel is full.
+    fn send(&mut self) -> io::Result<()> {
+        if self.chunk.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::replace(&mut self.chunk, Vec::with_capacity(WRITE_SIZE));
+        self.sender.blocking_send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the writer has failed"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.chunk.extend_from_slice(buf);
+        if self.chunk.len() >= WRITE_SIZE {
+            self.send()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Parses the input as it is read. The parser runs on Tokio's blocking
+// threads, reading from `de::from_reader`'s token reader, which is handed
+// each read as it completes; reading waits while the parser is behind, and
+// stops as soon as the parser is done, whether or not the input has ended.
+// Must be called from within a Tokio runtime.
+pub async fn from_async_reader<R, T>(mut reader: R) -> Result<T>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned + Send + 'static
+{
+    let (sender, receiver) = mpsc::channel(READ_AHEAD);
+    let mut parser = task::spawn_blocking(move || {
+        de::from_reader(ChannelReader { receiver, chunk: Vec::new(), offset: 0 })
+    });
+    let mut sender = Some(sender);
+    let mut buffer = vec![0; READ_SIZE];
+    let parsed = loop {
+        tokio::select! {
+            parsed = &mut parser => break parsed,
+            read = reader.read(&mut buffer), if sender.is_some() => {
+                let read = match read {
+                    Ok(0) => {
+                        sender = None;
+                        continue;
+                    },
+                    Ok(count) => Ok(buffer[..count].to_vec()),
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(error) => Err(error)
+                };
+                let failed = read.is_err();
+                // The parser hangs up once it has its value or an error.
+                if sender.as_ref().unwrap().send(read).await.is_err() || failed {
+                    sender = None;
+                }
+            }
+        }
+    };
+    join(parsed)
+}
+
+// The result of a blocking task, with its panic, if it had one, passed on.
+fn join<T>(joined: std::result::Result<Result<T>, task::JoinError>) -> Result<T> {
+    match joined {
+        Ok(result) => result,
+        Err(error) => match error.try_into_panic() {
+            Ok(panic) => std::panic::resume_unwind(panic),
+            Err(error) => Err(Error::Message(error.to_string()))
+        }
+    }
+}
+
+// The reads of `from_async_reader`, as the parser sees them.
+struct ChannelReader {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    // How much of `chunk` has been read.
+    offset: usize
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.chunk.len() {
+            match self.receiver.blocking_recv() {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.offset = 0;
+                },
+                Some(Err(error)) => return Err(error),
+                None => return Ok(0)
+            }
+        }
+        let count = buf.len().min(self.chunk.len() - self.offset);
+        buf[..count].copy_from_slice(&self.chunk[self.offset..self.offset + count]);
+        self.offset += count;
+        Ok(count)
+    }
+}
+
+// Reads a file of records like `StreamDeserializer`, from an async reader.
+// Input is read until it holds a whole record, which is then deserialized,
+// so only the record being read is kept in memory.
+pub struct AsyncStreamDeserializer<R, T> {
+    reader: R,
+    // Unparsed input, starting at `start`.
+    buffer: String,
+    // The start of a UTF-8 sequence split across reads.
+    pending: Vec<u8>,
+    start: Position,
+    scan: Scan,
+    eof: bool,
+    // Carries the environment and locals from one record to the next.
+    state: Deserializer<'static>,
+    records: VecDeque<(usize, Result<T>)>,
+    offset: usize,
+    // The input size limit, past which reading stops.
+    max_size: usize,
+    output: PhantomData<T>
+}
+
+// How far the search for the end of the next record has got.
+struct Scan {
+    // Where lexing resumes.
+    offset: usize,
+    depth: usize,
+    // Whether the last token ended a value outside of any brackets.
+    ended: bool,
+    // Whether the last token was a name.
+    after_name: bool
+}
+
+impl<R: AsyncRead + Unpin, T: DeserializeOwned> AsyncStreamDeserializer<R, T> {
+    pub fn new(reader: R) -> AsyncStreamDeserializer<R, T> {
+        let start = Position { offset: 0, line: 1, column: 1 };
+        AsyncStreamDeserializer {
+            reader,
+            buffer: String::new(),
+            pending: Vec::new(),
+            start,
+            scan: Scan { offset: 0, depth: 0, ended: false, after_name: false },
+            eof: false,
+            state: Deserializer::from_str(""),
+            records: VecDeque::new(),
+            offset: 0,
+            max_size: usize::MAX,
+            output: PhantomData
+        }
+    }
+
+    pub fn register_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(Vec<Value>) -> Result<Value> + 'static
+    {
+        self.state.register_function(name, function);
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.state.set_symbols(symbols);
+    }
+
+    pub fn set_loader<L: Loader + 'static>(&mut self, loader: L) {
+        self.state.set_loader(loader);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.max_size = limits.input_size;
+        self.state.set_limits(limits);
+    }
+
+    pub fn set_duplicate_keys(&mut self, duplicate_keys: DuplicateKeys) {
+        self.state.set_duplicate_keys(duplicate_keys);
+    }
+
+    pub fn set_zero_based_arrays(&mut self, enabled: bool) {
+        self.state.set_zero_based_arrays(enabled);
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        self.state.warnings()
+    }
+
+    // The byte offset of the start of the last record read.
+    pub fn byte_offset(&self) -> usize {
+        self.offset
+    }
+
+    // Reads the next record, or `None` at the end of the input. Errors in a
+    // record are returned in its place; errors reading the input end the
+    // stream.
+    pub async fn next(&mut self) -> Option<Result<T>> {
+        loop {
+            if let Some((offset, record)) = self.records.pop_front() {
+                self.offset = offset;
+                return Some(record);
+            }
+            if self.eof && self.buffer.is_empty() {
+                return None;
+            }
+            if let Some(end) = self.find_record_end() {
+                self.parse_records(end);
+                continue;
+            }
+            if self.eof {
+                let end = self.start.offset + self.buffer.len();
+                self.parse_records(end);
+                continue;
+            }
+            if let Err(error) = self.fill().await {
+                self.eof = true;
+                self.buffer.clear();
+                return Some(Err(error));
+            }
+        }
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        // Past the limit, the input is left for the deserializer to reject,
+        // after any records that fit. Part of a character keeps it past.
+        let read = self.start.offset + self.buffer.len() + self.pending.len();
+        if read > self.max_size {
+            self.eof = true;
+            if !self.pending.is_empty() {
+                self.buffer.push(char::REPLACEMENT_CHARACTER);
+                self.pending.clear();
+            }
+            return Ok(());
+        }
+        let room = self.max_size.saturating_add(1) - read;
+        let start = self.pending.len();
+        self.pending.resize(start + READ_SIZE.min(room), 0);
+        let count = self.reader.read(&mut self.pending[start..]).await;
+        let count = match count {
+            Ok(count) => count,
+            Err(error) => {
+                self.pending.truncate(start);
+                return Err(error.into());
+            }
+        };
+        self.pending.truncate(start + count);
+        if count == 0 {
+            self.eof = true;
+        }
+        let valid = match str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(error) if error.error_len().is_none() && !self.eof => error.valid_up_to(),
+            Err(error) => {
+                let offset = self.start.offset + self.buffer.len();
+                return Err(Error::InvalidUtf8 {
+                    valid_up_to: offset + error.valid_up_to(),
+                    error_len: error.error_len()
+                });
+            }
+        };
+        self.buffer.push_str(str::from_utf8(&self.pending[..valid]).unwrap());
+        self.pending.drain(..valid);
+        Ok(())
+    }
+
+    // Looks for the start of the record after the first one in the buffer,
+    // lexing only tokens that can't be continued by input yet to be read.
+    fn find_record_end(&mut self) -> Option<usize> {
+        let text = &self.buffer[self.scan.offset - self.start.offset..];
+        let mut lexer = Token::lexer(text);
+        let mut scanned = 0;
+        while let Some(token) = lexer.next() {
+            let span = lexer.span();
+            if !self.eof && span.end + LOOKAHEAD > text.len() {
+                break;
+            }
+            if self.scan.depth == 0 && self.scan.ended && starts_record(&token, self.scan.after_name) {
+                self.scan.offset += span.start;
+                return Some(self.scan.offset);
+            }
+            scanned = span.end;
+            if token == Token::Comment {
+                continue;
+            }
+            match token {
+                Token::LBrace | Token::LParen | Token::LBracket => self.scan.depth += 1,
+                Token::RBrace | Token::RParen | Token::RBracket => self.scan.depth = self.scan.depth.saturating_sub(1),
+                _ => {}
+            }
+            self.scan.ended = self.scan.depth == 0 && matches!(token,
+                Token::RBrace | Token::RParen | Token::Str(_) | Token::Integer(_) | Token::Float(_)
+                    | Token::Bool(_) | Token::Nil | Token::Name(_));
+            self.scan.after_name = matches!(token, Token::Name(_));
+        }
+        self.scan.offset += scanned;
+        None
+    }
+
+    // Deserializes the records in the buffer up to `end`.
+    fn parse_records(&mut self, end: usize) {
+        let len = end - self.start.offset;
+        {
+            let mut de = Deserializer::from_part(&self.buffer[..len], self.start);
+            de.swap_state(&mut self.state);
+            let mut stream = de.into_iter::<T>();
+            while let Some(record) = stream.next() {
+                self.records.push_back((stream.byte_offset(), record));
+            }
+            let mut de = stream.into_inner();
+            self.start = de.end_position();
+            de.swap_state(&mut self.state);
+            // A record past a limit ends the stream.
+            if de.stopped() {
+                self.eof = true;
+            }
+        }
+        if self.eof && self.start.offset < end {
+            self.buffer.clear();
+            self.pending.clear();
+            return;
+        }
+        self.buffer.drain(..len);
+        self.scan = Scan { offset: end, depth: 0, ended: false, after_name: false };
+    }
+}
+
+// Whether `token`, following a complete value, starts the next record
+// rather than continuing the current one.
+fn starts_record(token: &Token, after_name: bool) -> bool {
+    match token {
+        Token::LBrace | Token::Str(_) => !after_name,
+        Token::Name(_) | Token::Integer(_) | Token::Float(_) | Token::Bool(_) | Token::Nil
+            | Token::Local | Token::Do | Token::Return | Token::Minus => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    // Takes at most `size` bytes per write and is only ready for every
+    // other one.
+    struct SlowWriter {
+        output: Vec<u8>,
+        writes: usize,
+        size: usize,
+        ready: bool
+    }
+
+    impl AsyncWrite for SlowWriter {
+        fn poll_write(mut self: Pin<&mut Self>, context: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                context.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let count = buf.len().min(self.size);
+            self.output.extend_from_slice(&buf[..count]);
+            self.writes += 1;
+            Poll::Ready(Ok(count))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn table() -> BTreeMap<String, Vec<u32>> {
+        (0..500).map(|n| (format!("key{}", n), (0..n % 20).collect())).collect()
+    }
+
+    #[tokio::test]
+    async fn write_in_chunks() {
+        let value = table();
+        let mut writer = SlowWriter { output: Vec::new(), writes: 0, size: 1000, ready: false };
+        to_async_writer(value.clone(), &mut writer).await.unwrap();
+        assert_eq!(String::from_utf8(writer.output).unwrap(), ser::to_string(&value).unwrap());
+        assert!(writer.writes > 1);
+
+        let mut output = Vec::new();
+        to_async_writer_pretty(value.clone(), &mut output).await.unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), ser::to_string_pretty(&value).unwrap());
+    }
+
+    #[tokio::test]
+    async fn read_value() {
+        let value = table();
+        let text = ser::to_string(&value).unwrap();
+        let (mut sender, receiver) = tokio::io::duplex(100);
+        let writer = tokio::spawn(async move {
+            sender.write_all(text.as_bytes()).await.unwrap();
+        });
+        let read: BTreeMap<String, Vec<u32>> = from_async_reader(receiver).await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(read, value);
+    }
+
+    #[tokio::test]
+    async fn read_error_before_end_of_input() {
+        // The input is never closed, so this only returns if reading stops
+        // once the parser is done. The lexer needs a little text past a
+        // token to know it's complete, hence the spaces.
+        let (mut sender, receiver) = tokio::io::duplex(100);
+        sender.write_all(b"{ 1,, 2 }                ").await.unwrap();
+        let error = from_async_reader::<_, Vec<u32>>(receiver).await.unwrap_err();
+        assert_eq!(error.to_string(), "unexpected ','; expected a value at line 1, column 5");
+        let value = from_async_reader::<_, u32>(&b"return 1                "[..]).await.unwrap();
+        assert_eq!(value, 1);
+        drop(sender);
+    }
+
+    // Fails every write after the first.
+    struct FailingWriter {
+        writes: usize
+    }
+
+    impl AsyncWrite for FailingWriter {
+        fn poll_write(mut self: Pin<&mut Self>, _context: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            if self.writes > 1 {
+                return Poll::Ready(Err(io::Error::other("disk full")));
+            }
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _context: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn write_error() {
+        let mut writer = FailingWriter { writes: 0 };
+        let error = to_async_writer(table(), &mut writer).await.unwrap_err();
+        assert!(error.to_string().contains("disk full"), "{}", error);
+        assert_eq!(writer.writes, 2);
+    }
+
+    #[tokio::test]
+    async fn stream_records() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Entry {
+            name: String
+        }
+        let text = "log[#log+1] = { name = \"a\" }\nlocal b = \"b\"\nlog[#log+1] = { name = b }\n";
+        let mut stream = AsyncStreamDeserializer::<_, Entry>::new(text.as_bytes());
+        assert_eq!(stream.next().await.unwrap().unwrap(), Entry { name: "a".to_string() });
+        assert_eq!(stream.byte_offset(), 0);
+        assert_eq!(stream.next().await.unwrap().unwrap(), Entry { name: "b".to_string() });
+        assert_eq!(stream.byte_offset(), 43);
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -0,0 +1,2530 @@
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::marker::PhantomData;
+use std::str;
+
+use serde::de::{self, Deserialize, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::de::value::{BorrowedStrDeserializer, SeqDeserializer};
+
+use crate::error::{Error, Limit, Position, Result, Warning};
+use crate::loader::Loader;
+use crate::parser::{Span, Token, Tokens};
+use crate::spanned;
+use crate::symbols::Symbols;
+use crate::value::{Key, Table, Value};
+
+pub fn from_str<'a, T: Deserialize<'a>>(text: &'a str) -> Result<T> {
+    let mut deserializer = Deserializer::from_str(text);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+pub fn from_slice<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T> {
+    from_str(str::from_utf8(bytes)?)
+}
+
+// Reads a value from `reader` without holding all of its text in memory.
+pub fn from_reader<R: Read, T: DeserializeOwned>(reader: R) -> Result<T> {
+    let mut deserializer = Deserializer::from_reader(reader);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+// Returns every syntax error in `text` instead of stopping at the first; see
+// `Deserializer::check`.
+pub fn check(text: &str) -> Vec<Error> {
+    Deserializer::from_str(text).check()
+}
+
+// A host function callable from LSON, as in `rgb(255, 0, 0)`.
+pub type Function = Box<dyn Fn(Vec<Value>) -> Result<Value>>;
+
+// Limits on what the input can make the deserializer do, for reading input
+// that isn't trusted. Going past one is an `Error::LimitExceeded`.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Limits {
+    // How deeply tables and argument lists may nest, counting those of the
+    // files that include the input. The default of 128 keeps input like
+    // `{{{{...}}}}` from overflowing the stack.
+    pub depth: usize,
+    // In bytes, for each file.
+    pub input_size: usize,
+    // In bytes, after escapes are processed.
+    pub string_length: usize,
+    // Of any one table.
+    pub table_entries: usize,
+    // Names looked up, functions called and numbers negated, over the
+    // whole input and the files it includes.
+    pub evaluation_steps: usize
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            depth: 128,
+            input_size: usize::MAX,
+            string_length: usize::MAX,
+            table_entries: usize::MAX,
+            evaluation_steps: usize::MAX
+        }
+    }
+}
+
+// What to do about a key given twice in one table, like `a` in
+// `{ a = 1, a = 2 }` or `[1]` in `{ "x", [1] = "y" }`. Lua keeps the last
+// value.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum DuplicateKeys {
+    #[default]
+    Allow,
+    Warn,
+    Error
+}
+
+// What the host provides to the input, which included files share.
+#[derive(Default)]
+pub(crate) struct Environment {
+    functions: HashMap<String, Function>,
+    symbols: Symbols,
+    loader: Option<Box<dyn Loader>>,
+    // Values of the modules `require` has already loaded.
+    modules: HashMap<String, Value>,
+    limits: Limits,
+    // Evaluation steps taken so far.
+    steps: usize,
+    duplicate_keys: DuplicateKeys,
+    zero_based_arrays: bool,
+    warnings: Vec<Warning>
+}
+
+// Deserializes an LSON chunk: any number of `local` declarations and
+// `do ... end` blocks followed by either `return <expr>` or a bare
+// expression.
+pub struct Deserializer<'de> {
+    tokens: Tokens<'de>,
+    // Innermost scope last; each `do ... end` block pushes a scope.
+    scopes: Vec<HashMap<String, Value>>,
+    environment: Environment,
+    // Names of the files being included, outermost first.
+    includes: Vec<String>,
+    // How deeply nested the include of this input is in the including files.
+    base_depth: usize,
+    started: bool,
+    // Set when the chunk returns no value, which reads as `nil`.
+    empty: bool,
+    // Set when the value of the chunk isn't introduced by `return`.
+    bare: bool
+}
+
+impl<'de> Deserializer<'de> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &'de str) -> Deserializer<'de> {
+        Deserializer::with_tokens(Tokens::new(text))
+    }
+
+    // Reads the input in pieces as it is parsed. Strings are always owned,
+    // so nothing can be borrowed from the input.
+    pub fn from_reader<R: Read + 'de>(reader: R) -> Deserializer<'de> {
+        Deserializer::with_tokens(Tokens::from_reader(reader))
+    }
+
+    #[cfg(feature = "async")]
+    // Reads `text` as the part of a larger input that starts at `start`, so
+    // that positions refer to the whole input.
+    pub(crate) fn from_part(text: &'de str, start: Position) -> Deserializer<'de> {
+        Deserializer::with_tokens(Tokens::at(text, start))
+    }
+
+    #[cfg(feature = "async")]
+    // Exchanges the environment and locals with `other`, to carry them from
+    // one part of an input to the next.
+    pub(crate) fn swap_state(&mut self, other: &mut Deserializer) {
+        std::mem::swap(&mut self.environment, &mut other.environment);
+        std::mem::swap(&mut self.scopes, &mut other.scopes);
+        self.apply_limits();
+    }
+
+    #[cfg(feature = "async")]
+    // The position after the last token read.
+    pub(crate) fn end_position(&mut self) -> Position {
+        self.tokens.peek_span().start
+    }
+
+    #[cfg(feature = "async")]
+    // Whether reading stopped at an error, such as a limit being exceeded.
+    pub(crate) fn stopped(&self) -> bool {
+        self.tokens.read_error().is_some()
+    }
+
+    fn with_tokens(tokens: Tokens<'de>) -> Deserializer<'de> {
+        Deserializer {
+            tokens,
+            scopes: vec![HashMap::new()],
+            environment: Environment::default(),
+            includes: Vec::new(),
+            base_depth: 0,
+            started: false,
+            empty: false,
+            bare: false
+        }
+    }
+
+    // Makes `name` callable from the input. Calls take their arguments as
+    // `name(a, b)`, `name "string"` or `name { table }`; calling a name that
+    // hasn't been registered is an error.
+    pub fn register_function<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(Vec<Value>) -> Result<Value> + 'static
+    {
+        self.environment.functions.insert(name.to_string(), Box::new(function));
+    }
+
+    // Resolves dotted names like `Align.Center` that don't start with a
+    // local through `symbols`.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.environment.symbols = symbols;
+    }
+
+    // Resolves `require "module"` and `dofile "path"` through `loader`.
+    // Without a loader they are calls to unregistered functions, like any
+    // other.
+    pub fn set_loader<L: Loader + 'static>(&mut self, loader: L) {
+        self.environment.loader = Some(Box::new(loader));
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.environment.limits = limits;
+        self.apply_limits();
+    }
+
+    pub fn set_duplicate_keys(&mut self, duplicate_keys: DuplicateKeys) {
+        self.environment.duplicate_keys = duplicate_keys;
+    }
+
+    // Numbers positional items from 0 where they're read as map keys, to
+    // read back what `ser::Serializer::set_zero_based_arrays` writes. Keys
+    // written out, like `[5]`, are read as they are. A `Vec` reads positional
+    // items, or items keyed from `[1]` in order, with no gaps.
+    pub fn set_zero_based_arrays(&mut self, enabled: bool) {
+        self.environment.zero_based_arrays = enabled;
+    }
+
+    // The warnings about the input read so far, including the files it
+    // includes: duplicate keys if they're warned about, fields the target
+    // type doesn't have, and tables that mix positional items with keys.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.environment.warnings
+    }
+
+    fn apply_limits(&mut self) {
+        let limits = self.environment.limits;
+        self.tokens.set_limits(limits.input_size, limits.string_length);
+    }
+
+    // Reads the input as a sequence of records, such as `Entry { ... }` calls
+    // or bare tables, instead of as a single value.
+    #[allow(clippy::should_implement_trait)]
+    pub fn into_iter<T: Deserialize<'de>>(self) -> StreamDeserializer<'de, T> {
+        StreamDeserializer::new(self)
+    }
+
+    // Checks that nothing but the `end`s of enclosing blocks follows the
+    // returned value.
+    pub fn end(&mut self) -> Result<()> {
+        self.start()?;
+        if self.tokens.peek() == Some(&Token::Semicolon) {
+            self.tokens.next();
+        }
+        while self.scopes.len() > 1 {
+            self.expect(Token::End, "'end'")?;
+            self.scopes.pop();
+        }
+        match self.tokens.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.unexpected("end of input"))
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if !self.started {
+            self.started = true;
+            self.parse_statements()?;
+        }
+        Ok(())
+    }
+
+    // Runs a `local`, `do`, `end` or empty statement. Returns false if the
+    // next token doesn't start one of those.
+    fn parse_statement(&mut self) -> Result<bool> {
+        match self.tokens.peek() {
+            Some(Token::Local) => self.parse_local()?,
+            Some(Token::Do) => {
+                self.tokens.next();
+                self.scopes.push(HashMap::new());
+            },
+            Some(Token::End) if self.scopes.len() > 1 => {
+                self.tokens.next();
+                self.scopes.pop();
+            },
+            Some(Token::Semicolon) => {
+                self.tokens.next();
+            },
+            _ => return Ok(false)
+        }
+        Ok(true)
+    }
+
+    // Runs the statements leading up to the value of the chunk.
+    fn parse_statements(&mut self) -> Result<()> {
+        while self.parse_statement()? {}
+        match self.tokens.peek() {
+            Some(Token::Return) => {
+                self.tokens.next();
+                if let None | Some(Token::End) | Some(Token::Semicolon) = self.tokens.peek() {
+                    self.empty = true;
+                }
+            },
+            None if self.scopes.len() > 1 => return Err(self.unexpected("'end'")),
+            None => self.empty = true,
+            Some(_) if self.scopes.len() > 1 => return Err(self.unexpected("a statement")),
+            Some(_) => self.bare = true
+        }
+        Ok(())
+    }
+
+    // local name [<const>] {, name [<const>]} [= expr {, expr}]
+    fn parse_local(&mut self) -> Result<()> {
+        self.tokens.next();
+        let mut names = vec![];
+        loop {
+            names.push(self.expect_name()?.to_string());
+            if self.tokens.peek() == Some(&Token::Less) {
+                self.tokens.next();
+                let span = self.tokens.peek_span();
+                let attribute = self.expect_name()?;
+                if attribute != "const" {
+                    return Err(self.syntax_error(span.start, format!("unsupported attribute '{}'", attribute)));
+                }
+                self.expect(Token::Greater, "'>'")?;
+            }
+            if self.tokens.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.tokens.next();
+        }
+
+        let mut values = vec![];
+        if self.tokens.peek() == Some(&Token::Assign) {
+            self.tokens.next();
+            loop {
+                values.push(self.parse_value()?);
+                if self.tokens.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.tokens.next();
+            }
+        }
+
+        // Names are bound only after every expression has been evaluated, so
+        // `local x = x` refers to an outer `x`.
+        let mut values = values.into_iter();
+        let scope = self.scopes.last_mut().unwrap();
+        for name in names {
+            scope.insert(name, values.next().unwrap_or(Value::Nil));
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    // Evaluates an expression into a `Value`.
+    fn parse_value(&mut self) -> Result<Value> {
+        if self.empty {
+            return Ok(Value::Nil);
+        }
+        let (token, span) = match self.tokens.next() {
+            Some(next) => next,
+            None => return Err(self.unexpected("a value"))
+        };
+        if let Token::Name(_) = token {
+            self.step(span.start)?;
+        }
+        match token {
+            Token::Nil => Ok(Value::Nil),
+            Token::Bool(b) => Ok(Value::Boolean(b)),
+            Token::Integer(i) => self.parse_number(i.to_i128()
+                .map(Value::Integer)
+                .unwrap_or(Value::Float(i.to_f64()))),
+            Token::Float(f) => self.parse_number(Value::Float(f)),
+            Token::Str(s) => Ok(Value::String(s.into_owned())),
+            Token::LBrace => {
+                self.check_depth(span.start)?;
+                self.parse_table().map(Value::Table)
+            },
+            Token::Name(name) if self.at_arguments() => self.parse_call(&name, span.start),
+            Token::Name(name) if self.tokens.peek() == Some(&Token::Dot) => self.parse_dotted(&name, span.start),
+            Token::Name(name) if self.tokens.peek() == Some(&Token::Colon) => {
+                self.tokens.next();
+                let method = self.expect_name()?;
+                self.parse_call(&format!("{}:{}", name, method), span.start)
+            },
+            Token::Name(name) => match self.lookup(&name) {
+                Some(value) => Ok(value.clone()),
+                None => Err(Error::UnresolvedName { name: name.into_owned(), position: span.start })
+            },
+            // Negations are applied in a loop, so that long runs of them
+            // can't overflow the stack.
+            Token::Minus => {
+                let mut negations = vec![span.start];
+                while self.tokens.peek() == Some(&Token::Minus) {
+                    negations.push(self.tokens.next().unwrap().1.start);
+                }
+                let mut value = self.parse_value()?;
+                for position in negations.into_iter().rev() {
+                    self.step(position)?;
+                    value = match value {
+                        Value::Integer(i) => i.checked_neg().map(Value::Integer).unwrap_or(Value::Float(-(i as f64))),
+                        Value::Float(f) => Value::Float(-f),
+                        value => {
+                            let message = format!("attempt to negate a {} value", value.type_name());
+                            return Err(self.syntax_error(position, message));
+                        }
+                    };
+                }
+                Ok(value)
+            },
+            token => Err(self.unexpected_token(&token, &span, "a value"))
+        }
+    }
+
+    // Reads what follows a number literal. NaN and the infinities are
+    // written as `0/0`, `1/0` and `-1/0`, so a literal may be divided by
+    // another; as in Lua, the quotient is always a float.
+    fn parse_number(&mut self, number: Value) -> Result<Value> {
+        if self.tokens.peek() != Some(&Token::Slash) {
+            return Ok(number);
+        }
+        self.tokens.next();
+        let divisor = match self.tokens.peek() {
+            Some(Token::Integer(i)) => i.to_f64(),
+            Some(Token::Float(f)) => *f,
+            _ => return Err(self.unexpected("a number"))
+        };
+        self.tokens.next();
+        match number {
+            Value::Integer(i) => Ok(Value::Float(i as f64 / divisor)),
+            Value::Float(f) => Ok(Value::Float(f / divisor)),
+            _ => unreachable!()
+        }
+    }
+
+    // Evaluates `name.field...`. Fields of locals are looked up like in Lua;
+    // any other dotted name must be a symbol, or a registered function when
+    // called.
+    fn parse_dotted(&mut self, name: &str, position: Position) -> Result<Value> {
+        let mut path = name.to_string();
+        let mut fields = vec![];
+        while self.tokens.peek() == Some(&Token::Dot) {
+            self.tokens.next();
+            let field = self.expect_name()?;
+            path.push('.');
+            path.push_str(&field);
+            fields.push(field);
+        }
+        if self.at_arguments() && self.lookup(name).is_none() {
+            return self.parse_call(&path, position);
+        }
+
+        if let Some(value) = self.lookup(name) {
+            let mut value = value;
+            for field in fields {
+                value = match value {
+                    Value::Table(table) => table.get(&Key::String(field.to_string())).unwrap_or(&Value::Nil),
+                    value => {
+                        let message = format!("attempt to index a {} value (field '{}')", value.type_name(), field);
+                        return Err(self.syntax_error(position, message));
+                    }
+                };
+            }
+            return Ok(value.clone());
+        }
+
+        match self.environment.symbols.get(&path) {
+            Some(value) => Ok(value.clone()),
+            None => Err(Error::UnresolvedName { name: path, position })
+        }
+    }
+
+    fn at_arguments(&mut self) -> bool {
+        matches!(self.tokens.peek(), Some(Token::LParen) | Some(Token::Str(_)) | Some(Token::LBrace))
+    }
+
+    fn parse_call(&mut self, name: &str, position: Position) -> Result<Value> {
+        if let Some(value) = self.lookup(name) {
+            let message = format!("attempt to call a {} value (local '{}')", value.type_name(), name);
+            return Err(self.syntax_error(position, message));
+        }
+        let arguments = self.parse_arguments()?;
+        match self.environment.functions.get(name) {
+            Some(function) => function(arguments).map_err(|e| e.at(position)),
+            None if self.environment.loader.is_some() && (name == "require" || name == "dofile") =>
+                self.include(name, arguments, position),
+            None => Err(Error::UnknownFunction { name: name.to_string(), position })
+        }
+    }
+
+    // Evaluates the chunk named by a `require` or `dofile` call. Required
+    // modules are loaded once; later calls reuse their value.
+    fn include(&mut self, function: &str, arguments: Vec<Value>, position: Position) -> Result<Value> {
+        let target = match arguments.as_slice() {
+            [Value::String(target)] => target.clone(),
+            _ => {
+                let message = format!("bad argument #1 to '{}' (string expected)", function);
+                return Err(Error::Data { message, position });
+            }
+        };
+        let require = function == "require";
+        if require {
+            if let Some(value) = self.environment.modules.get(&target) {
+                return Ok(value.clone());
+            }
+        }
+        let loader = self.environment.loader.as_ref().expect("include without a loader");
+        let source = if require { loader.require(&target) } else { loader.dofile(&target) };
+        let source = source.map_err(|error| match error.position() {
+            Some(_) => error,
+            None => Error::Data { message: error.to_string(), position }
+        })?;
+        if self.includes.contains(&source.name) {
+            let mut chain = self.includes.clone();
+            chain.push(source.name);
+            let message = format!("include cycle: {}", chain.join(" -> "));
+            return Err(Error::Data { message, position });
+        }
+
+        let mut included = Deserializer::from_str(&source.text);
+        included.environment = std::mem::take(&mut self.environment);
+        included.apply_limits();
+        included.base_depth = self.base_depth + self.tokens.depth();
+        included.includes = self.includes.clone();
+        included.includes.push(source.name.clone());
+        let result = Value::deserialize(&mut included)
+            .and_then(|value| included.end().map(|_| value));
+        self.environment = included.environment;
+
+        let value = result.map_err(|error| Error::Include {
+            file: source.name.clone(),
+            position,
+            error: Box::new(error)
+        })?;
+        if require {
+            self.environment.modules.insert(target, value.clone());
+        }
+        Ok(value)
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Value>> {
+        match self.tokens.next() {
+            Some((Token::Str(s), _)) => Ok(vec![Value::String(s.into_owned())]),
+            Some((Token::LBrace, span)) => {
+                self.check_depth(span.start)?;
+                Ok(vec![Value::Table(self.parse_table()?)])
+            },
+            Some((Token::LParen, span)) => {
+                self.check_depth(span.start)?;
+                let mut arguments = vec![];
+                if self.tokens.peek() != Some(&Token::RParen) {
+                    loop {
+                        arguments.push(self.parse_value()?);
+                        if self.tokens.peek() != Some(&Token::Comma) {
+                            break;
+                        }
+                        self.tokens.next();
+                    }
+                }
+                self.expect(Token::RParen, "')'")?;
+                Ok(arguments)
+            },
+            _ => Err(self.unexpected("function arguments"))
+        }
+    }
+
+    // The name of a constructor call such as `Label { ... }` or
+    // `Point(1, 2)` at the next token: a call to a name that is neither a
+    // local nor a registered function, nor an include if there is a loader.
+    fn peek_constructor(&mut self) -> Option<Cow<'de, str>> {
+        if self.empty {
+            return None;
+        }
+        let name = match self.tokens.peek() {
+            Some(Token::Name(name)) => name.clone(),
+            _ => return None
+        };
+        match self.tokens.peek_nth(1) {
+            Some(Token::LParen) | Some(Token::LBrace) | Some(Token::Str(_)) => {},
+            _ => return None
+        }
+        let include = self.environment.loader.is_some() && (name == "require" || name == "dofile");
+        if include || self.lookup(&name).is_some() || self.environment.functions.contains_key(name.as_ref()) {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    // Whether the next token is the name of a local that isn't `nil`, used
+    // on its own. Such a value can be read like any other, with its span.
+    fn peek_set_local(&mut self) -> bool {
+        let name = match self.tokens.peek() {
+            Some(Token::Name(name)) => name.clone(),
+            _ => return false
+        };
+        let alone = !matches!(self.tokens.peek_nth(1),
+            Some(Token::Dot) | Some(Token::Colon) | Some(Token::LParen) | Some(Token::LBrace) | Some(Token::Str(_)));
+        alone && !matches!(self.lookup(&name), None | Some(Value::Nil))
+    }
+
+    // Whether the next tokens are the `list[#list+1] =` that
+    // `RecordStyle::Append` writes before each record.
+    fn peek_append(&mut self) -> bool {
+        let list = match self.tokens.peek() {
+            Some(Token::Name(name)) => name.clone(),
+            _ => return false
+        };
+        self.tokens.peek_nth(1) == Some(&Token::LBracket)
+            && self.tokens.peek_nth(2) == Some(&Token::Length)
+            && self.tokens.peek_nth(3) == Some(&Token::Name(list))
+            && self.tokens.peek_nth(4) == Some(&Token::Plus)
+            && matches!(self.tokens.peek_nth(5), Some(Token::Integer(i)) if i.to_i128() == Some(1))
+            && self.tokens.peek_nth(6) == Some(&Token::RBracket)
+            && self.tokens.peek_nth(7) == Some(&Token::Assign)
+    }
+
+    // Consumes `list[#list+1] =` if it is next.
+    fn skip_append(&mut self) {
+        if self.peek_append() {
+            for _ in 0..8 {
+                self.tokens.next();
+            }
+        }
+    }
+
+    // Whether the next tokens start a method call like `data:extend{ ... }`.
+    fn peek_method_call(&mut self) -> bool {
+        matches!(self.tokens.peek(), Some(Token::Name(_)))
+            && self.tokens.peek_nth(1) == Some(&Token::Colon)
+            && matches!(self.tokens.peek_nth(2), Some(Token::Name(_)))
+    }
+
+    // Consumes the name of a constructor call, which must be `expected`.
+    fn expect_constructor(&mut self, expected: &str) -> Result<()> {
+        let span = self.tokens.peek_span();
+        let name = self.expect_name()?;
+        if name == expected {
+            Ok(())
+        } else {
+            Err(Error::Data {
+                message: format!("expected a '{}' constructor, found '{}'", expected, name),
+                position: span.start
+            })
+        }
+    }
+
+    // Reads the arguments of a constructor for a record without data: `()`
+    // or `{}`.
+    fn constructor_unit(&mut self) -> Result<()> {
+        let (open, close, description) = match self.tokens.peek() {
+            Some(Token::LParen) => (Token::LParen, Token::RParen, "')'"),
+            _ => (Token::LBrace, Token::RBrace, "'}'")
+        };
+        self.expect(open, "'(' or '{'")?;
+        self.expect(close, description)?;
+        Ok(())
+    }
+
+    // Reads constructor arguments as a sequence: `(a, b)`, `{ a, b }` or a
+    // single string.
+    fn constructor_seq<V: Visitor<'de>>(&mut self, visitor: V) -> Result<V::Value> {
+        match self.tokens.peek() {
+            Some(Token::LParen) => self.deserialize_arguments(visitor),
+            Some(Token::Str(_)) => {
+                let position = self.tokens.peek_span().start;
+                let mut arguments = Table::new();
+                arguments.push(self.parse_value()?);
+                de::Deserializer::deserialize_seq(Value::Table(arguments), visitor)
+                    .map_err(|e| e.at(position))
+            },
+            _ => de::Deserializer::deserialize_seq(self, visitor)
+        }
+    }
+
+    // Reads constructor arguments for a struct: a table of fields, or the
+    // fields in order as `(a, b)`.
+    fn constructor_map<V: Visitor<'de>>(&mut self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        match self.tokens.peek() {
+            Some(Token::LParen) => self.deserialize_arguments(visitor),
+            _ => self.deserialize_table(visitor, false, Some(fields))
+        }
+    }
+
+    // Reads a table through `visitor.visit_map`. Positional items are read as
+    // integer keys only if `positional` is set, since derived structs would
+    // take them as field indexes. Names other than `fields`, if given, are
+    // warned about.
+    fn deserialize_table<V: Visitor<'de>>(
+        &mut self,
+        visitor: V,
+        positional: bool,
+        fields: Option<&'static [&'static str]>
+    ) -> Result<V::Value> {
+        self.start()?;
+        let position = self.tokens.peek_span().start;
+        if !self.empty && self.tokens.peek() == Some(&Token::LBrace) {
+            self.tokens.next();
+            self.check_depth(position)?;
+            let mut access = TableAccess::new(&mut *self);
+            access.positional_keys = positional;
+            access.fields = fields;
+            let value = visitor.visit_map(access).map_err(|e| e.at(position))?;
+            self.end_table()?;
+            Ok(value)
+        } else {
+            let value = self.parse_value()?;
+            de::Deserializer::deserialize_map(value, visitor).map_err(|e| e.at(position))
+        }
+    }
+
+    fn deserialize_arguments<V: Visitor<'de>>(&mut self, visitor: V) -> Result<V::Value> {
+        let position = self.tokens.peek_span().start;
+        self.expect(Token::LParen, "'('")?;
+        self.check_depth(position)?;
+        let value = visitor.visit_seq(ArgumentsAccess { de: &mut *self, first: true })
+            .map_err(|e| e.at(position))?;
+        self.expect(Token::RParen, "')'")?;
+        Ok(value)
+    }
+
+    // Evaluates the rest of a table constructor after its `{`.
+    fn parse_table(&mut self) -> Result<Table> {
+        let mut table = Table::new();
+        let mut positional = 0;
+        let mut entries = 0;
+        let mut keyed = false;
+        let mut mixed = false;
+        loop {
+            if self.tokens.peek() == Some(&Token::RBrace) {
+                self.tokens.next();
+                return Ok(table);
+            }
+            entries += 1;
+            self.check_entries(entries)?;
+            let position = self.tokens.peek_span().start;
+            let key = if let Some(name) = self.tokens.peek_field_name() {
+                self.tokens.next();
+                self.tokens.next();
+                keyed = true;
+                Key::String(name.into_owned())
+            } else if self.tokens.peek() == Some(&Token::LBracket) {
+                self.tokens.next();
+                let span = self.tokens.peek_span();
+                let key = self.parse_value()?;
+                self.expect(Token::RBracket, "']'")?;
+                self.expect(Token::Assign, "'='")?;
+                keyed = true;
+                self.table_key(key, &span)?
+            } else {
+                positional += 1;
+                Key::Integer(positional)
+            };
+            if keyed && positional > 0 && !mixed {
+                mixed = true;
+                self.warn(MIXED_TABLE.to_string(), position);
+            }
+            if table.get(&key).is_some() {
+                self.duplicate_key(&key, position)?;
+            }
+            table.insert(key, self.parse_value()?);
+            self.table_separator()?;
+        }
+    }
+
+    fn table_key(&self, key: Value, span: &Span) -> Result<Key> {
+        let message = match &key {
+            Value::Nil => "table index is nil",
+            Value::Float(f) if f.is_nan() => "table index is NaN",
+            Value::Table(_) => "tables are not supported as table keys",
+            _ => return Ok(key.into_key().unwrap())
+        };
+        Err(self.syntax_error(span.start, message.to_string()))
+    }
+
+    // Consumes the `,` or `;` after a table entry, if there is one.
+    fn table_separator(&mut self) -> Result<()> {
+        match self.tokens.peek() {
+            Some(Token::Comma) | Some(Token::Semicolon) => {
+                self.tokens.next();
+                Ok(())
+            },
+            Some(Token::RBrace) => Ok(()),
+            _ => Err(self.unexpected("',' or '}'"))
+        }
+    }
+
+    fn expect(&mut self, expected: Token<'de>, description: &str) -> Result<Span> {
+        if self.tokens.peek() == Some(&expected) {
+            Ok(self.tokens.next().unwrap().1)
+        } else {
+            Err(self.unexpected(description))
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<Cow<'de, str>> {
+        if let Some(Token::Name(_)) = self.tokens.peek() {
+            if let Some((Token::Name(name), _)) = self.tokens.next() {
+                return Ok(name);
+            }
+        }
+        Err(self.unexpected("a name"))
+    }
+
+    // Reports the next token as unexpected.
+    fn unexpected(&mut self, expected: &str) -> Error {
+        let span = self.tokens.peek_span();
+        match self.tokens.peek().cloned() {
+            Some(token) => self.unexpected_token(&token, &span, expected),
+            None => self.syntax_error(span.start, format!("unexpected end of input; expected {}", expected))
+        }
+    }
+
+    fn unexpected_token(&self, token: &Token<'de>, span: &Span, expected: &str) -> Error {
+        if let (Token::Error, Some(error)) = (token, self.tokens.read_error()) {
+            return error.clone();
+        }
+        let message = match token {
+            Token::Error => self.tokens.describe_error(span),
+            token => format!("unexpected {}; expected {}", token, expected)
+        };
+        self.syntax_error(span.start, message)
+    }
+
+    // Checks the nesting depth after the bracket at `position` is consumed.
+    fn check_depth(&self, position: Position) -> Result<()> {
+        let max = self.environment.limits.depth;
+        if self.base_depth + self.tokens.depth() > max {
+            return Err(Error::LimitExceeded { limit: Limit::Depth, max, position });
+        }
+        Ok(())
+    }
+
+    // Checks the size of a table whose next token starts its entry number
+    // `entries`.
+    fn check_entries(&mut self, entries: usize) -> Result<()> {
+        let max = self.environment.limits.table_entries;
+        if entries > max {
+            let position = self.tokens.peek_span().start;
+            return Err(Error::LimitExceeded { limit: Limit::TableEntries, max, position });
+        }
+        Ok(())
+    }
+
+    fn warn(&mut self, message: String, position: Position) {
+        let file = self.includes.last().cloned();
+        self.environment.warnings.push(Warning { message, position, file });
+    }
+
+    // Handles a second entry for `key`, at `position`, in one table.
+    fn duplicate_key(&mut self, key: &Key, position: Position) -> Result<()> {
+        let message = format!("duplicate key {} in table", key);
+        match self.environment.duplicate_keys {
+            DuplicateKeys::Allow => Ok(()),
+            DuplicateKeys::Warn => {
+                self.warn(message, position);
+                Ok(())
+            },
+            DuplicateKeys::Error => Err(Error::Data { message, position })
+        }
+    }
+
+    // Counts an evaluation step taken at `position`.
+    fn step(&mut self, position: Position) -> Result<()> {
+        let max = self.environment.limits.evaluation_steps;
+        self.environment.steps += 1;
+        if self.environment.steps > max {
+            return Err(Error::LimitExceeded { limit: Limit::EvaluationSteps, max, position });
+        }
+        Ok(())
+    }
+
+    fn syntax_error(&self, position: Position, message: String) -> Error {
+        Error::Syntax { message, position }
+    }
+
+    fn end_table(&mut self) -> Result<()> {
+        self.expect(Token::RBrace, "'}'").map(|_| ())
+    }
+}
+
+// Checking the syntax of the input without reading it into a value. After an
+// error, checking resumes at the next `,`, `;` or `}` of the table it was in,
+// or at the next statement.
+impl<'de> Deserializer<'de> {
+    // Checks the syntax of the input and returns the errors found, in order.
+    // Nothing is evaluated, so names and calls needn't resolve. Errors reading
+    // the input end the check.
+    pub fn check(mut self) -> Vec<Error> {
+        let mut errors = Vec::new();
+        let mut blocks = 0;
+        let mut returned = false;
+        loop {
+            let start = self.tokens.peek_span().start.offset;
+            let result = match self.tokens.peek() {
+                None => break,
+                Some(Token::Do) => {
+                    self.tokens.next();
+                    blocks += 1;
+                    Ok(())
+                },
+                Some(Token::End) if blocks > 0 => {
+                    self.tokens.next();
+                    blocks -= 1;
+                    Ok(())
+                },
+                // Records may be separated by `,` as well as `;`.
+                Some(Token::Semicolon) | Some(Token::Comma) => {
+                    self.tokens.next();
+                    Ok(())
+                },
+                Some(Token::Local) => self.check_local(&mut errors),
+                Some(Token::Return) if !returned => {
+                    self.tokens.next();
+                    returned = true;
+                    match self.tokens.peek() {
+                        None | Some(Token::End) | Some(Token::Semicolon) => Ok(()),
+                        _ => self.check_value(&mut errors)
+                    }
+                },
+                Some(_) if returned && blocks > 0 => Err(self.unexpected("'end'")),
+                Some(_) if returned => Err(self.unexpected("end of input")),
+                Some(_) => {
+                    self.skip_append();
+                    self.check_value(&mut errors)
+                }
+            };
+            if let Err(error) = result {
+                add_error(&mut errors, error);
+                if self.tokens.read_error().is_some() {
+                    return errors;
+                }
+                self.skip_to_sync(start, false);
+            }
+        }
+        if blocks > 0 {
+            add_error(&mut errors, self.unexpected("'end'"));
+        }
+        errors
+    }
+
+    fn check_local(&mut self, errors: &mut Vec<Error>) -> Result<()> {
+        self.tokens.next();
+        loop {
+            self.expect_name()?;
+            if self.tokens.peek() == Some(&Token::Less) {
+                self.tokens.next();
+                let span = self.tokens.peek_span();
+                let attribute = self.expect_name()?;
+                if attribute != "const" {
+                    return Err(self.syntax_error(span.start, format!("unsupported attribute '{}'", attribute)));
+                }
+                self.expect(Token::Greater, "'>'")?;
+            }
+            if self.tokens.peek() != Some(&Token::Comma) {
+                break;
+            }
+            self.tokens.next();
+        }
+        if self.tokens.peek() == Some(&Token::Assign) {
+            self.tokens.next();
+            loop {
+                self.check_value(errors)?;
+                if self.tokens.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.tokens.next();
+            }
+        }
+        Ok(())
+    }
+
+    // Tokens that can't start a value are left for recovery to skip, since
+    // they may be what it resumes at.
+    fn check_value(&mut self, errors: &mut Vec<Error>) -> Result<()> {
+        let span = self.tokens.peek_span();
+        match self.tokens.peek() {
+            Some(Token::Nil) | Some(Token::Bool(_)) | Some(Token::Str(_)) => {
+                self.tokens.next();
+                Ok(())
+            },
+            Some(Token::Integer(_)) | Some(Token::Float(_)) => {
+                self.tokens.next();
+                if self.tokens.peek() == Some(&Token::Slash) {
+                    self.tokens.next();
+                    match self.tokens.peek() {
+                        Some(Token::Integer(_)) | Some(Token::Float(_)) => {
+                            self.tokens.next();
+                        },
+                        _ => return Err(self.unexpected("a number"))
+                    }
+                }
+                Ok(())
+            },
+            Some(Token::LBrace) => {
+                self.tokens.next();
+                self.check_nesting(span.start)?;
+                self.check_table(errors)
+            },
+            Some(Token::Name(_)) => {
+                self.tokens.next();
+                while self.tokens.peek() == Some(&Token::Dot) {
+                    self.tokens.next();
+                    self.expect_name()?;
+                }
+                if self.tokens.peek() == Some(&Token::Colon) {
+                    self.tokens.next();
+                    self.expect_name()?;
+                    return self.check_arguments(errors);
+                }
+                if self.at_arguments() {
+                    return self.check_arguments(errors);
+                }
+                Ok(())
+            },
+            Some(Token::Minus) => {
+                while self.tokens.peek() == Some(&Token::Minus) {
+                    self.tokens.next();
+                }
+                self.check_value(errors)
+            },
+            _ => Err(self.unexpected("a value"))
+        }
+    }
+
+    // Checks the depth after the bracket at `position` is consumed. Input
+    // that is too deep is skipped up to the bracket that closes it.
+    fn check_nesting(&mut self, position: Position) -> Result<()> {
+        let depth = self.tokens.depth();
+        self.check_depth(position).inspect_err(|_| {
+            while self.tokens.depth() >= depth && self.tokens.next().is_some() {}
+        })
+    }
+
+    fn check_arguments(&mut self, errors: &mut Vec<Error>) -> Result<()> {
+        match self.tokens.next() {
+            Some((Token::Str(_), _)) => Ok(()),
+            Some((Token::LBrace, span)) => {
+                self.check_nesting(span.start)?;
+                self.check_table(errors)
+            },
+            Some((Token::LParen, span)) => {
+                self.check_nesting(span.start)?;
+                let depth = self.tokens.depth();
+                self.check_argument_list(errors).inspect_err(|_| {
+                    self.skip_brackets(depth, &[Token::RBrace]);
+                })
+            },
+            _ => Err(self.unexpected("function arguments"))
+        }
+    }
+
+    fn check_argument_list(&mut self, errors: &mut Vec<Error>) -> Result<()> {
+        if self.tokens.peek() != Some(&Token::RParen) {
+            loop {
+                self.check_value(errors)?;
+                if self.tokens.peek() != Some(&Token::Comma) {
+                    break;
+                }
+                self.tokens.next();
+            }
+        }
+        self.expect(Token::RParen, "')'")?;
+        Ok(())
+    }
+
+    // Checks the rest of a table constructor after its `{`. Errors in its
+    // entries are added to `errors`; a table that isn't closed before the
+    // next statement or the end of the input is an error of the table.
+    fn check_table(&mut self, errors: &mut Vec<Error>) -> Result<()> {
+        loop {
+            match self.tokens.peek() {
+                Some(Token::RBrace) => {
+                    self.tokens.next();
+                    return Ok(());
+                },
+                None | Some(Token::Local) | Some(Token::Do) | Some(Token::Return) | Some(Token::End) =>
+                    return Err(self.unexpected("'}'")),
+                _ => {}
+            }
+            let start = self.tokens.peek_span().start.offset;
+            if let Err(error) = self.check_entry(errors) {
+                if self.tokens.read_error().is_some() {
+                    return Err(error);
+                }
+                add_error(errors, error);
+                self.skip_to_sync(start, true);
+                continue;
+            }
+            // A missing separator is taken as a typo, and the next entry is
+            // checked as usual. A `)` or `]` is taken as a mistyped `}`.
+            if let Err(error) = self.table_separator() {
+                if self.tokens.read_error().is_some() {
+                    return Err(error);
+                }
+                add_error(errors, error);
+                if let Some(Token::RParen) | Some(Token::RBracket) = self.tokens.peek() {
+                    self.tokens.next();
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn check_entry(&mut self, errors: &mut Vec<Error>) -> Result<()> {
+        if self.tokens.peek_field_name().is_some() {
+            self.tokens.next();
+            self.tokens.next();
+        } else if self.tokens.peek() == Some(&Token::LBracket) {
+            self.tokens.next();
+            let depth = self.tokens.depth();
+            self.check_value(errors)
+                .and_then(|_| self.expect(Token::RBracket, "']'"))
+                .inspect_err(|_| {
+                    let stops = [Token::Comma, Token::Semicolon, Token::RBrace, Token::Assign];
+                    self.skip_brackets(depth, &stops);
+                })?;
+            self.expect(Token::Assign, "'='")?;
+        }
+        self.check_value(errors)
+    }
+
+    // Skips the rest of the entry or statement that started at `start` and
+    // had an error: through the next `,` or `;`, or up to the `}` of the
+    // table it's in or the start of a statement. Outside tables, a call such
+    // as `Item { ... }` starts a statement too. At least one token is
+    // skipped.
+    fn skip_to_sync(&mut self, start: usize, in_table: bool) {
+        let depth = self.tokens.depth();
+        if self.tokens.peek_span().start.offset == start {
+            self.tokens.next();
+        }
+        loop {
+            let nested = self.tokens.depth() > depth;
+            if !nested && !in_table && self.at_call_statement() {
+                return;
+            }
+            match self.tokens.peek() {
+                None | Some(Token::Local) | Some(Token::Do) | Some(Token::Return) | Some(Token::End) => return,
+                Some(Token::RBrace) if !nested && in_table => return,
+                Some(Token::Comma) | Some(Token::Semicolon) if !nested => {
+                    self.tokens.next();
+                    return;
+                },
+                _ => {}
+            }
+            self.tokens.next();
+        }
+    }
+
+    // Whether a name is next that is called, as `f(...)`, `f{...}`, `f"..."`
+    // or `data:extend{...}`, or appended to, as `list[#list+1] =`.
+    fn at_call_statement(&mut self) -> bool {
+        if !matches!(self.tokens.peek(), Some(Token::Name(_))) {
+            return false;
+        }
+        matches!(self.tokens.peek_nth(1), Some(Token::LParen) | Some(Token::LBrace) | Some(Token::Str(_)))
+            || self.peek_method_call()
+            || self.peek_append()
+    }
+
+    // After an error in brackets that are `depth` deep, skips up to their
+    // closing bracket, which is consumed, or up to the start of a statement
+    // or one of `stops` in them.
+    fn skip_brackets(&mut self, depth: usize, stops: &[Token]) {
+        while self.tokens.depth() >= depth {
+            let inside = self.tokens.depth() == depth;
+            match self.tokens.peek() {
+                None | Some(Token::Local) | Some(Token::Do) | Some(Token::Return) | Some(Token::End) => return,
+                Some(token) if inside && stops.contains(token) => return,
+                _ => {}
+            }
+            self.tokens.next();
+        }
+    }
+}
+
+// Adds `error` unless there is already one at its position, as there is when
+// several unclosed tables end at the end of the input.
+fn add_error(errors: &mut Vec<Error>, error: Error) {
+    if errors.last().map(Error::position) != Some(error.position()) {
+        errors.push(error);
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    // Strings go to the visitor as they are, so those without escapes can be
+    // borrowed.
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.start()?;
+        let position = self.tokens.peek_span().start;
+        if let (false, Some(Token::Str(_))) = (self.empty, self.tokens.peek()) {
+            if let Some((Token::Str(s), _)) = self.tokens.next() {
+                let value: Result<V::Value> = match s {
+                    Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+                    Cow::Owned(s) => visitor.visit_string(s)
+                };
+                return value.map_err(|e| e.at(position));
+            }
+        }
+        let value = self.parse_value()?;
+        value.deserialize_any(visitor).map_err(|e| e.at(position))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.start()?;
+        if self.empty || self.tokens.peek() == Some(&Token::Nil) {
+            self.tokens.next();
+            visitor.visit_none()
+        } else if self.peek_constructor().is_some() || self.peek_set_local() {
+            visitor.visit_some(self)
+        } else if let Some(Token::Name(_)) = self.tokens.peek() {
+            let position = self.tokens.peek_span().start;
+            let value = self.parse_value()?;
+            value.deserialize_option(visitor).map_err(|e| e.at(position))
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // A chunk made of record statements like `Entry { ... }` reads as a
+    // sequence of those records.
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let top_level = !self.started;
+        self.start()?;
+        let position = self.tokens.peek_span().start;
+        let records = self.peek_constructor().is_some() || self.peek_method_call() || self.peek_append();
+        if top_level && self.bare && records {
+            visitor.visit_seq(RecordsAccess { de: &mut *self, extend: None })
+                .map_err(|e| e.at(position))
+        } else if !self.empty && self.tokens.peek() == Some(&Token::LBrace) {
+            self.tokens.next();
+            self.check_depth(position)?;
+            let value = visitor.visit_seq(TableAccess::new(&mut *self))
+                .map_err(|e| e.at(position))?;
+            self.end_table()?;
+            Ok(value)
+        } else {
+            let value = self.parse_value()?;
+            value.deserialize_seq(visitor).map_err(|e| e.at(position))
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, name: &'static str, visitor: V) -> Result<V::Value> {
+        self.start()?;
+        if self.peek_constructor().is_some() {
+            self.expect_constructor(name)?;
+            self.constructor_unit()?;
+            visitor.visit_unit()
+        } else {
+            self.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _len: usize,
+        visitor: V
+    ) -> Result<V::Value> {
+        self.start()?;
+        if self.peek_constructor().is_some() {
+            self.expect_constructor(name)?;
+            self.constructor_seq(visitor)
+        } else {
+            self.deserialize_seq(visitor)
+        }
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_table(visitor, true, None)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        self.start()?;
+        if name == spanned::NAME && fields == spanned::FIELDS {
+            let start = self.tokens.peek_span().start;
+            return visitor.visit_map(SpannedAccess { de: &mut *self, start, end: None, field: 0 });
+        }
+        if self.peek_constructor().is_some() {
+            self.expect_constructor(name)?;
+            self.constructor_map(fields, visitor)
+        } else {
+            self.deserialize_table(visitor, false, Some(fields))
+        }
+    }
+
+    // Enums are read the way `ser` writes them: unit variants as strings and
+    // everything else as `{ Variant = value }`, or as constructor calls like
+    // `Variant(value)`.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        self.start()?;
+        let position = self.tokens.peek_span().start;
+        if self.peek_constructor().is_some() {
+            visitor.visit_enum(ConstructorAccess { de: &mut *self })
+                .map_err(|e| e.at(position))
+        } else if !self.empty && self.tokens.peek() == Some(&Token::LBrace) {
+            self.tokens.next();
+            self.check_depth(position)?;
+            let value = visitor.visit_enum(TableAccess::new(&mut *self))
+                .map_err(|e| e.at(position))?;
+            self.end_table()?;
+            Ok(value)
+        } else {
+            let value = self.parse_value()?;
+            value.deserialize_enum(name, variants, visitor).map_err(|e| e.at(position))
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.start()?;
+        self.parse_value()?;
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit identifier
+    }
+}
+
+const MIXED_TABLE: &str = "table mixes positional items with keys";
+
+// Passes a name to `seed`, borrowed from the input when possible.
+fn deserialize_name<'de, S: DeserializeSeed<'de>>(seed: S, name: Cow<'de, str>) -> Result<S::Value> {
+    match name {
+        Cow::Borrowed(name) => seed.deserialize(BorrowedStrDeserializer::new(name)),
+        Cow::Owned(name) => seed.deserialize(name.into_deserializer())
+    }
+}
+
+// Reads the entries of a table constructor whose `{` has been consumed. The
+// closing `}` is left for the caller.
+struct TableAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    // Whether positional items are read as map entries keyed by their index.
+    positional_keys: bool,
+    // How many positional items have been read, or for a sequence how many
+    // items of either form.
+    items: i128,
+    // Whether a sequence has had an item written as `[n] = value`.
+    keyed: bool,
+    entries: usize,
+    // The fields of the struct being read, if it is one.
+    fields: Option<&'static [&'static str]>,
+    // Whether a map has had a key written out, and has been warned about
+    // mixing those with positional items.
+    explicit: bool,
+    mixed: bool,
+    // The keys of a map read so far, if duplicates are looked for.
+    keys: Option<HashSet<Key>>
+}
+
+impl<'a, 'de> TableAccess<'a, 'de> {
+    fn new(de: &'a mut Deserializer<'de>) -> TableAccess<'a, 'de> {
+        let keys = match de.environment.duplicate_keys {
+            DuplicateKeys::Allow => None,
+            _ => Some(HashSet::new())
+        };
+        TableAccess {
+            de,
+            positional_keys: false,
+            items: 0,
+            keyed: false,
+            entries: 0,
+            fields: None,
+            explicit: false,
+            mixed: false,
+            keys
+        }
+    }
+
+    // Notes the key of the map entry at `position`, building it with `key`
+    // only if it's needed to look for duplicates.
+    fn note_key<F: FnOnce() -> Key>(&mut self, positional: bool, key: F, position: Position) -> Result<()> {
+        self.explicit |= !positional;
+        if self.explicit && self.items > 0 && !self.mixed {
+            self.mixed = true;
+            self.de.warn(MIXED_TABLE.to_string(), position);
+        }
+        if let Some(keys) = &mut self.keys {
+            let key = key();
+            if keys.contains(&key) {
+                return self.de.duplicate_key(&key, position);
+            }
+            keys.insert(key);
+        }
+        Ok(())
+    }
+
+    // Warns about a name that isn't one of the fields of the struct.
+    fn check_field(&mut self, name: &str, position: Position) {
+        if let Some(fields) = self.fields {
+            if !fields.contains(&name) {
+                self.de.warn(format!("unknown field '{}'", name), position);
+            }
+        }
+    }
+
+    // Counts the entry that starts at the next token, unless the table ends
+    // there.
+    fn count_entry(&mut self) -> Result<()> {
+        if self.de.tokens.peek() == Some(&Token::RBrace) {
+            return Ok(());
+        }
+        self.entries += 1;
+        self.de.check_entries(self.entries)
+    }
+
+    // Reads `name =` or `[expr] =` through `seed`. Returns `None` at the end
+    // of the table.
+    fn key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let position = self.de.tokens.peek_span().start;
+        if let Some(name) = self.de.tokens.peek_field_name() {
+            self.de.tokens.next();
+            self.de.tokens.next();
+            self.check_field(&name, position);
+            self.note_key(false, || Key::String(name.to_string()), position)?;
+            return deserialize_name(seed, name).map(Some);
+        }
+        match self.de.tokens.peek() {
+            Some(Token::RBrace) => Ok(None),
+            Some(Token::LBracket) => {
+                self.de.tokens.next();
+                let key = self.bracketed_key(seed, position)?;
+                self.de.expect(Token::RBracket, "']'")?;
+                self.de.expect(Token::Assign, "'='")?;
+                Ok(Some(key))
+            },
+            _ => Err(self.de.unexpected("'name =' or '[key] ='"))
+        }
+    }
+
+    // Reads the expression in `[expr] =`. Keys are normalized the way Lua
+    // does it, so `[1.0]` is the integer key `[1]`. Strings go to `seed`
+    // directly so they can be borrowed. Warnings about the key are given at
+    // `position`, the `[` of the entry.
+    fn bracketed_key<K: DeserializeSeed<'de>>(&mut self, seed: K, position: Position) -> Result<K::Value> {
+        let span = self.de.tokens.peek_span();
+        if let Some(Token::Str(name)) = self.de.tokens.peek().cloned() {
+            self.check_field(&name, position);
+            self.note_key(false, || Key::String(name.into_owned()), position)?;
+            return seed.deserialize(&mut *self.de);
+        }
+        let key = self.de.parse_value()?;
+        let key = self.de.table_key(key, &span)?;
+        if let Key::String(name) = &key {
+            self.check_field(name, position);
+        }
+        self.note_key(false, || key.clone(), position)?;
+        seed.deserialize(Value::from(key)).map_err(|e| e.at(span.start))
+    }
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for TableAccess<'a, 'de> {
+    type Error = Error;
+
+    // Items may also be written with their indexes, as `[1] = a, [2] = b`,
+    // as long as they're in order. Positional items can't follow those, since
+    // Lua would number them from 1 again.
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        self.count_entry()?;
+        if self.de.tokens.peek_field_name().is_some() {
+            return Err(self.de.unexpected("a list item"));
+        }
+        match self.de.tokens.peek() {
+            Some(Token::RBrace) => return Ok(None),
+            Some(Token::LBracket) => {
+                self.de.tokens.next();
+                let span = self.de.tokens.peek_span();
+                let key = self.de.parse_value()?;
+                let key = self.de.table_key(key, &span)?;
+                if key != Key::Integer(self.items + 1) {
+                    let message = format!("expected the key [{}] for the next list item", self.items + 1);
+                    return Err(self.de.syntax_error(span.start, message));
+                }
+                self.de.expect(Token::RBracket, "']'")?;
+                self.de.expect(Token::Assign, "'='")?;
+                self.keyed = true;
+            },
+            _ if self.keyed => {
+                let expected = format!("'[{}] ='", self.items + 1);
+                return Err(self.de.unexpected(&expected));
+            },
+            _ => {}
+        }
+        self.items += 1;
+        let value = seed.deserialize(&mut *self.de)?;
+        self.de.table_separator()?;
+        Ok(Some(value))
+    }
+}
+
+impl<'a, 'de> de::MapAccess<'de> for TableAccess<'a, 'de> {
+    type Error = Error;
+
+    // Positional items are keyed by their index, starting at 1, or at 0 with
+    // zero-based arrays.
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        self.count_entry()?;
+        let item = self.de.tokens.peek_field_name().is_none()
+            && !matches!(self.de.tokens.peek(), None | Some(Token::RBrace) | Some(Token::LBracket));
+        if item && self.positional_keys {
+            self.items += 1;
+            let position = self.de.tokens.peek_span().start;
+            let index = if self.de.environment.zero_based_arrays { self.items - 1 } else { self.items };
+            self.note_key(true, || Key::Integer(index), position)?;
+            return seed.deserialize(Value::Integer(index))
+                .map(Some)
+                .map_err(|e| e.at(position));
+        }
+        self.key_seed(seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = seed.deserialize(&mut *self.de)?;
+        self.de.table_separator()?;
+        Ok(value)
+    }
+}
+
+// `{ Variant = value }`
+impl<'a, 'de> de::EnumAccess<'de> for TableAccess<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(mut self, seed: V) -> Result<(V::Value, Self)> {
+        match self.key_seed(seed)? {
+            Some(variant) => Ok((variant, self)),
+            None => Err(self.de.unexpected("a variant"))
+        }
+    }
+}
+
+impl<'a, 'de> de::VariantAccess<'de> for TableAccess<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        <()>::deserialize(&mut *self.de)?;
+        self.de.table_separator()
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        let value = seed.deserialize(&mut *self.de)?;
+        self.de.table_separator()?;
+        Ok(value)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        let value = de::Deserializer::deserialize_seq(&mut *self.de, visitor)?;
+        self.de.table_separator()?;
+        Ok(value)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        let value = self.de.deserialize_table(visitor, false, Some(fields))?;
+        self.de.table_separator()?;
+        Ok(value)
+    }
+}
+
+// Reads the arguments of a constructor call like `Point(1, 2)` whose `(` has
+// been consumed.
+struct ArgumentsAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    first: bool
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for ArgumentsAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.de.tokens.peek() == Some(&Token::RParen) {
+            return Ok(None);
+        }
+        if !self.first {
+            self.de.expect(Token::Comma, "',' or ')'")?;
+        }
+        self.first = false;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+// `Variant(args)` or `Variant { ... }`
+struct ConstructorAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for ConstructorAccess<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let name = self.de.expect_name()?;
+        let variant = deserialize_name(seed, name)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'a, 'de> de::VariantAccess<'de> for ConstructorAccess<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        self.de.constructor_unit()
+    }
+
+    // `Variant(value)`, or the table or string of `Variant { ... }` and
+    // `Variant "..."`.
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        if self.de.tokens.peek() == Some(&Token::LParen) {
+            let (_, span) = self.de.tokens.next().unwrap();
+            self.de.check_depth(span.start)?;
+            let value = seed.deserialize(&mut *self.de)?;
+            self.de.expect(Token::RParen, "')'")?;
+            Ok(value)
+        } else {
+            seed.deserialize(&mut *self.de)
+        }
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        self.de.constructor_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        self.de.constructor_map(fields, visitor)
+    }
+}
+
+// Reads a value as a `Spanned`: the value, then where it started and ended.
+struct SpannedAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    start: Position,
+    end: Option<Position>,
+    field: usize
+}
+
+impl<'a, 'de> de::MapAccess<'de> for SpannedAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match spanned::FIELDS.get(self.field) {
+            Some(field) => seed.deserialize(BorrowedStrDeserializer::new(field)).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        self.field += 1;
+        let position = match spanned::FIELDS[self.field - 1] {
+            spanned::VALUE => {
+                let value = seed.deserialize(&mut *self.de)?;
+                // A chunk that returns nothing has a value without any text.
+                self.end = Some(self.de.tokens.last_end().max(self.start));
+                return Ok(value);
+            },
+            spanned::START => self.start,
+            _ => self.end.unwrap_or(self.start)
+        };
+        let parts = vec![position.offset, position.line, position.column];
+        seed.deserialize(SeqDeserializer::new(parts.into_iter()))
+    }
+}
+
+// Reads a chunk of record statements as a sequence. Each statement is a
+// record such as `Entry { ... }` or `list[#list+1] = { ... }`, or a method
+// call like `data:extend{ ... }` whose table argument holds any number of
+// records. `local` statements may appear between records.
+struct RecordsAccess<'a, 'de: 'a> {
+    de: &'a mut Deserializer<'de>,
+    // While inside the table of a method call, whether the table was
+    // wrapped in parentheses.
+    extend: Option<bool>
+}
+
+impl<'a, 'de> de::SeqAccess<'de> for RecordsAccess<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        loop {
+            if let Some(parenthesized) = self.extend {
+                if self.de.tokens.peek() == Some(&Token::RBrace) {
+                    self.de.tokens.next();
+                    if parenthesized {
+                        self.de.expect(Token::RParen, "')'")?;
+                    }
+                    self.extend = None;
+                    continue;
+                }
+                let value = seed.deserialize(&mut *self.de)?;
+                self.de.table_separator()?;
+                return Ok(Some(value));
+            }
+            if self.de.parse_statement()? {
+                continue;
+            }
+            if self.de.peek_method_call() {
+                for _ in 0..3 {
+                    self.de.tokens.next();
+                }
+                let parenthesized = self.de.tokens.peek() == Some(&Token::LParen);
+                if parenthesized {
+                    self.de.tokens.next();
+                }
+                self.de.expect(Token::LBrace, "'{'")?;
+                self.extend = Some(parenthesized);
+                continue;
+            }
+            if self.de.tokens.peek().is_none() {
+                return Ok(None);
+            }
+            self.de.skip_append();
+            return seed.deserialize(&mut *self.de).map(Some);
+        }
+    }
+}
+
+// Reads the records of a file one at a time, including those written by
+// `ser::RecordWriter`. `local` statements may appear between records, and
+// records may be separated by `,` or `;`.
+//
+// A record that fails to deserialize yields an error, after which the
+// stream moves on to the next record. Errors reading the input end the
+// stream.
+pub struct StreamDeserializer<'de, T> {
+    de: Deserializer<'de>,
+    offset: usize,
+    failed: bool,
+    output: PhantomData<T>
+}
+
+impl<'de, T: Deserialize<'de>> StreamDeserializer<'de, T> {
+    pub fn new(mut de: Deserializer<'de>) -> StreamDeserializer<'de, T> {
+        de.started = true;
+        StreamDeserializer { de, offset: 0, failed: false, output: PhantomData }
+    }
+
+    // The byte offset of the start of the last record read, or of the end of
+    // the input once the stream is done.
+    pub fn byte_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        self.de.warnings()
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn into_inner(self) -> Deserializer<'de> {
+        self.de
+    }
+
+    fn next_record(&mut self) -> Result<Option<T>> {
+        while self.de.parse_statement()? {}
+        self.offset = self.de.tokens.peek_span().start.offset;
+        if self.de.tokens.peek().is_none() {
+            return Ok(None);
+        }
+        self.de.skip_append();
+        let value = T::deserialize(&mut self.de)?;
+        if let Some(Token::Comma) | Some(Token::Semicolon) = self.de.tokens.peek() {
+            self.de.tokens.next();
+        }
+        Ok(Some(value))
+    }
+
+    // Skips the rest of the record that failed, which began at `start`:
+    // anything left inside its brackets, the arguments of a constructor
+    // whose name was read, or a token that couldn't start a record at all.
+    fn recover(&mut self, start: usize) {
+        let tokens = &mut self.de.tokens;
+        let mut skipped = tokens.peek_span().start.offset != start;
+        loop {
+            while tokens.depth() > 0 && tokens.next().is_some() {}
+            let after_name = tokens.after_name();
+            match tokens.peek() {
+                None => break,
+                Some(Token::Comma) | Some(Token::Semicolon) => {
+                    tokens.next();
+                    break;
+                },
+                Some(Token::LBrace) | Some(Token::LParen) | Some(Token::Str(_)) if after_name => {},
+                Some(_) if !skipped => {},
+                Some(_) => break
+            }
+            tokens.next();
+            skipped = true;
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Iterator for StreamDeserializer<'de, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.failed {
+            return None;
+        }
+        self.offset = self.de.tokens.peek_span().start.offset;
+        let result = self.next_record();
+        if result.is_err() {
+            if self.de.tokens.read_error().is_some() {
+                self.failed = true;
+            } else {
+                self.recover(self.offset);
+            }
+        }
+        result.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use crate::loader::MemoryLoader;
+
+    use super::*;
+
+    fn pos(offset: usize, line: usize, column: usize) -> Position {
+        Position { offset, line, column }
+    }
+
+    #[test]
+    fn locals() {
+        let text = "local RED <const> = { 255, 0, 0 }\nreturn { fg = RED, border = RED }";
+        let value: BTreeMap<String, Vec<u8>> = from_str(text).unwrap();
+        assert_eq!(value["fg"], vec![255, 0, 0]);
+        assert_eq!(value["border"], vec![255, 0, 0]);
+    }
+
+    #[test]
+    fn several_locals() {
+        let value: Vec<Option<i64>> = from_str("local a, b, c = 1, 2\nreturn { a, b, c }").unwrap();
+        assert_eq!(value, vec![Some(1), Some(2), None]);
+    }
+
+    #[test]
+    fn local_scopes() {
+        let text = "local x = 1\ndo local x = 2 local y = x end\nreturn x";
+        assert_eq!(from_str::<i64>(text).unwrap(), 1);
+        let text = "local x = 1 do local x = { x } end return x";
+        assert_eq!(from_str::<i64>(text).unwrap(), 1);
+        let text = "local x = 1 do local x = { x } return x end";
+        assert_eq!(from_str::<Vec<i64>>(text).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn bare_value() {
+        assert_eq!(from_str::<Vec<i64>>("local x = 3 { x, -x }").unwrap(), vec![3, -3]);
+        assert_eq!(from_str::<Option<i64>>("").unwrap(), None);
+        assert_eq!(from_str::<Option<i64>>("return").unwrap(), None);
+    }
+
+    #[test]
+    fn unresolved_name() {
+        let error = from_str::<Vec<i64>>("local a = 1\nreturn { a, b }").unwrap_err();
+        assert_eq!(error, Error::UnresolvedName { name: "b".to_string(), position: pos(24, 2, 13) });
+    }
+
+    #[test]
+    fn scope_ends() {
+        let error = from_str::<i64>("do local y = 1 end return y").unwrap_err();
+        assert_eq!(error, Error::UnresolvedName { name: "y".to_string(), position: pos(26, 1, 27) });
+        assert!(from_str::<i64>("do return 1").is_err());
+        assert!(from_str::<i64>("return 1 2").is_err());
+    }
+
+    fn rgb(arguments: Vec<Value>) -> Result<Value> {
+        let mut table = Table::new();
+        for argument in arguments {
+            match argument {
+                Value::Integer(i) if (0..256).contains(&i) => table.push(Value::Integer(i)),
+                _ => return Err(Error::Message("bad color component".to_string()))
+            }
+        }
+        Ok(Value::Table(table))
+    }
+
+    #[test]
+    fn functions() {
+        let mut de = Deserializer::from_str("{ fg = rgb(255, 0, 0), home = env 'HOME', n = count { 1, 2 } }");
+        de.register_function("rgb", rgb);
+        de.register_function("env", |arguments| match arguments.as_slice() {
+            [Value::String(name)] if name == "HOME" => Ok(Value::String("/home/lson".to_string())),
+            _ => Ok(Value::Nil)
+        });
+        de.register_function("count", |arguments| match arguments.as_slice() {
+            [Value::Table(table)] => Ok(Value::Integer(table.len() as i128)),
+            _ => Err(Error::Message("expected a table".to_string()))
+        });
+        let value = BTreeMap::<String, Value>::deserialize(&mut de).unwrap();
+        de.end().unwrap();
+        assert_eq!(Vec::<u8>::deserialize(value["fg"].clone()).unwrap(), vec![255, 0, 0]);
+        assert_eq!(value["home"], Value::String("/home/lson".to_string()));
+        assert_eq!(value["n"], Value::Integer(2));
+    }
+
+    #[test]
+    fn function_errors() {
+        let mut de = Deserializer::from_str("{ rgb(1, 2, 300) }");
+        de.register_function("rgb", rgb);
+        let error = Vec::<Vec<u8>>::deserialize(&mut de).unwrap_err();
+        assert_eq!(error, Error::Data { message: "bad color component".to_string(), position: pos(2, 1, 3) });
+
+        let error = from_str::<Vec<u8>>("rgb(1, 2, 3)").unwrap_err();
+        assert_eq!(error, Error::UnknownFunction { name: "rgb".to_string(), position: pos(0, 1, 1) });
+        let error = from_str::<Value>("{ os.exit(1) }").unwrap_err();
+        assert_eq!(error, Error::UnknownFunction { name: "os.exit".to_string(), position: pos(2, 1, 3) });
+    }
+
+    #[test]
+    fn calling_a_local() {
+        let error = from_str::<Value>("local f = 1 return f(2)").unwrap_err();
+        let message = "attempt to call a number value (local 'f')".to_string();
+        assert_eq!(error, Error::Syntax { message, position: pos(19, 1, 20) });
+    }
+
+    #[derive(Debug,PartialEq,Deserialize)]
+    struct Label {
+        name: String,
+        address: u32
+    }
+
+    #[derive(Debug,PartialEq,Deserialize)]
+    struct Point(i32, i32);
+
+    #[derive(Debug,PartialEq,Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Line(Point, Point),
+        Rect { width: u32, height: u32 }
+    }
+
+    #[test]
+    fn constructor_calls() {
+        let label: Label = from_str("Label { name = 'start', address = 0x10 }").unwrap();
+        assert_eq!(label, Label { name: "start".to_string(), address: 16 });
+        let label: Label = from_str("Label('start', 16)").unwrap();
+        assert_eq!(label, Label { name: "start".to_string(), address: 16 });
+        assert_eq!(from_str::<Point>("Point(1, -2)").unwrap(), Point(1, -2));
+        assert_eq!(from_str::<Point>("Point { 1, -2 }").unwrap(), Point(1, -2));
+        assert_eq!(from_str::<Point>("{ 1, -2 }").unwrap(), Point(1, -2));
+    }
+
+    #[test]
+    fn variant_calls() {
+        let shapes: Vec<Shape> = from_str(
+            "{ Empty(), Circle(1.5), Line(Point(0, 0), Point(1, 1)), Rect { width = 2, height = 3 } }").unwrap();
+        assert_eq!(shapes, vec![
+            Shape::Empty,
+            Shape::Circle(1.5),
+            Shape::Line(Point(0, 0), Point(1, 1)),
+            Shape::Rect { width: 2, height: 3 }
+        ]);
+        let shapes: Vec<Shape> = from_str("{ 'Empty', { Circle = 2.0 }, { Rect = { width = 1, height = 1 } } }").unwrap();
+        assert_eq!(shapes, vec![Shape::Empty, Shape::Circle(2.0), Shape::Rect { width: 1, height: 1 }]);
+    }
+
+    #[test]
+    fn wrong_constructor() {
+        let error = from_str::<Label>("{ Label { name = 'a', address = 1 } }").unwrap_err();
+        assert!(error.position().is_some());
+        let error = from_str::<Label>("Point { name = 'a', address = 1 }").unwrap_err();
+        let message = "expected a 'Label' constructor, found 'Point'".to_string();
+        assert_eq!(error, Error::Data { message, position: pos(0, 1, 1) });
+    }
+
+    #[test]
+    fn record_files() {
+        let text = "Label { name = 'a', address = 1 }\nLabel { name = 'b', address = 2 }\n\
+                    data:extend{ Label { name = 'c', address = 3 }, Label('d', 4) }";
+        let labels: Vec<Label> = from_str(text).unwrap();
+        let names: Vec<_> = labels.iter().map(|label| label.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "c", "d"]);
+
+        let shapes: Vec<Shape> = from_str("Circle(1.0)\nlocal w = 4\nRect { width = w, height = w }").unwrap();
+        assert_eq!(shapes, vec![Shape::Circle(1.0), Shape::Rect { width: 4, height: 4 }]);
+    }
+
+    #[derive(Debug,PartialEq,Deserialize)]
+    enum Align {
+        Left,
+        Center
+    }
+
+    fn symbols() -> Symbols {
+        let mut symbols = Symbols::new();
+        symbols.add_enum::<Align>("Align").unwrap();
+        symbols.add_constants("Color", vec![("Red", Value::Integer(0xff0000))]);
+        symbols
+    }
+
+    #[test]
+    fn symbol_references() {
+        let mut de = Deserializer::from_str("{ align = Align.Center, color = Color.Red }");
+        de.set_symbols(symbols());
+        #[derive(Deserialize)]
+        struct Style {
+            align: Align,
+            color: u32
+        }
+        let style = Style::deserialize(&mut de).unwrap();
+        assert_eq!(style.align, Align::Center);
+        assert_eq!(style.color, 0xff0000);
+    }
+
+    #[test]
+    fn unknown_symbol() {
+        let mut de = Deserializer::from_str("{\n  Align.Left,\n  Align.Top\n}");
+        de.set_symbols(symbols());
+        let error = Vec::<Align>::deserialize(&mut de).unwrap_err();
+        assert_eq!(error, Error::UnresolvedName { name: "Align.Top".to_string(), position: pos(18, 3, 3) });
+    }
+
+    #[test]
+    fn local_fields() {
+        let text = "local Colors = { red = 1, dark = { red = 2 } }\nreturn { Colors.red, Colors.dark.red }";
+        assert_eq!(from_str::<Vec<i64>>(text).unwrap(), vec![1, 2]);
+        assert!(from_str::<Value>("local n = 1 return n.x").is_err());
+    }
+
+    #[derive(Debug,PartialEq,Deserialize)]
+    struct Colors {
+        fg: Vec<u8>,
+        bg: Vec<u8>
+    }
+
+    #[derive(Debug,PartialEq,Deserialize)]
+    struct Config {
+        base: Colors,
+        labels: Vec<Label>
+    }
+
+    fn loader() -> MemoryLoader {
+        let mut loader = MemoryLoader::new();
+        loader.add_module("common.colors", "local BLACK = { 0, 0, 0 }\nreturn { fg = { 255, 255, 255 }, bg = BLACK }");
+        loader.add_file("labels.lua", "{ { name = 'a', address = 1 } }");
+        loader.add_module("a", "return { b = require 'b' }");
+        loader.add_module("b", "return { a = require 'a' }");
+        loader.add_module("broken", "return {\n  1,\n  x\n}");
+        loader
+    }
+
+    #[test]
+    fn includes() {
+        let mut de = Deserializer::from_str("return { base = require('common.colors'), labels = dofile 'labels.lua' }");
+        de.set_loader(loader());
+        let config = Config::deserialize(&mut de).unwrap();
+        de.end().unwrap();
+        assert_eq!(config.base, Colors { fg: vec![255, 255, 255], bg: vec![0, 0, 0] });
+        assert_eq!(config.labels, vec![Label { name: "a".to_string(), address: 1 }]);
+    }
+
+    #[test]
+    fn include_as_struct() {
+        let mut de = Deserializer::from_str("require 'common.colors'");
+        de.set_loader(loader());
+        let colors = Colors::deserialize(&mut de).unwrap();
+        assert_eq!(colors.bg, vec![0, 0, 0]);
+
+        let mut de = Deserializer::from_str("dofile('labels.lua')");
+        de.set_loader(loader());
+        assert_eq!(Vec::<Label>::deserialize(&mut de).unwrap().len(), 1);
+
+        // Without a loader, `require` is an unknown constructor.
+        let error = from_str::<Colors>("require 'common.colors'").unwrap_err();
+        let message = "expected a 'Colors' constructor, found 'require'".to_string();
+        assert_eq!(error, Error::Data { message, position: pos(0, 1, 1) });
+    }
+
+    #[test]
+    fn include_errors() {
+        let mut de = Deserializer::from_str("{ require 'a' }");
+        de.set_loader(loader());
+        match Value::deserialize(&mut de).unwrap_err() {
+            Error::Include { file, error, .. } => {
+                assert_eq!(file, "a");
+                let message = "include cycle: a -> b -> a".to_string();
+                assert!(matches!(*error, Error::Include { error, .. } if *error == Error::Data { message, position: pos(13, 1, 14) }));
+            },
+            error => panic!("unexpected {:?}", error)
+        }
+
+        let mut de = Deserializer::from_str("{\n  broken = require 'broken' }");
+        de.set_loader(loader());
+        let error = Value::deserialize(&mut de).unwrap_err();
+        let inner = Error::UnresolvedName { name: "x".to_string(), position: pos(16, 3, 3) };
+        assert_eq!(error, Error::Include { file: "broken".to_string(), position: pos(13, 2, 12), error: Box::new(inner) });
+
+        let mut de = Deserializer::from_str("require 'missing'");
+        de.set_loader(loader());
+        let error = Value::deserialize(&mut de).unwrap_err();
+        let message = "module 'missing' not found".to_string();
+        assert_eq!(error, Error::Data { message, position: pos(0, 1, 1) });
+    }
+
+    // Reads `chunk` bytes at a time, so that tokens and characters are split
+    // across reads.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        chunk: usize
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let count = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..count].copy_from_slice(&self.data[..count]);
+            self.data = &self.data[count..];
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn from_reader_and_slice() {
+        let text = "local name = 'caf\u{e9} \\u{2603}'\n-- a comment\nreturn { name, 1.5e3, -0x10, [[long\nstring]], 'caf\u{e9}' }";
+        let expected: Value = from_str(text).unwrap();
+        assert_eq!(from_slice::<Value>(text.as_bytes()).unwrap(), expected);
+        for chunk in 1..8 {
+            let reader = Trickle { data: text.as_bytes(), chunk };
+            assert_eq!(from_reader::<_, Value>(reader).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn reader_positions() {
+        let text = "{\n  1,\n  x\n}";
+        let expected = Error::UnresolvedName { name: "x".to_string(), position: pos(9, 3, 3) };
+        assert_eq!(from_reader::<_, Value>(Trickle { data: text.as_bytes(), chunk: 1 }).unwrap_err(), expected);
+        assert_eq!(from_slice::<Value>(text.as_bytes()).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let bytes = b"return 'ab\xffc'";
+        let expected = Error::InvalidUtf8 { valid_up_to: 10, error_len: Some(1) };
+        assert_eq!(from_slice::<String>(bytes).unwrap_err(), expected);
+        assert_eq!(from_reader::<_, String>(Trickle { data: bytes, chunk: 3 }).unwrap_err(), expected);
+        let expected = Error::InvalidUtf8 { valid_up_to: 9, error_len: None };
+        assert_eq!(from_reader::<_, String>(&b"return 'a\xc3"[..]).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn reader_errors() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk on fire"))
+            }
+        }
+        assert_eq!(from_reader::<_, Value>(Failing).unwrap_err(), Error::IoError("disk on fire".to_string()));
+    }
+
+    #[test]
+    fn long_tokens_from_reader() {
+        // Read in small pieces, re-lexing the string after every read would
+        // take far too long.
+        let body = "x".repeat(4 << 20);
+        let text = format!("return '{}' --[[ {} ]]", body, body);
+        let value: String = from_reader(Trickle { data: text.as_bytes(), chunk: 1024 }).unwrap();
+        assert_eq!(value, body);
+    }
+
+    #[test]
+    fn borrowed_strings() {
+        #[derive(Deserialize)]
+        struct Entry<'a> {
+            name: &'a str,
+            #[serde(borrow)]
+            note: Cow<'a, str>,
+            #[serde(borrow)]
+            escaped: Cow<'a, str>
+        }
+        let text = "{ name = 'plain', note = [[long\nstring]], escaped = 'tab\\there' }";
+        let entry: Entry = from_str(text).unwrap();
+        assert_eq!(entry.name, "plain");
+        assert!(matches!(entry.note, Cow::Borrowed("long\nstring")));
+        assert!(matches!(entry.escaped, Cow::Owned(ref s) if s == "tab\there"));
+
+        let start = text.as_ptr() as usize;
+        let name = entry.name.as_ptr() as usize;
+        assert!(name > start && name < start + text.len());
+    }
+
+    #[test]
+    fn borrowed_keys() {
+        let text = "{ alpha = 1, ['beta gamma'] = 2 }";
+        let map: BTreeMap<&str, i64> = from_str(text).unwrap();
+        assert_eq!(map["alpha"], 1);
+        assert_eq!(map["beta gamma"], 2);
+    }
+
+    #[test]
+    fn escaped_strings_are_owned() {
+        let error = from_str::<&str>("'a\\nb'").unwrap_err();
+        assert!(error.position().is_some());
+        assert_eq!(from_str::<String>("'a\\nb'").unwrap(), "a\nb");
+        // Nothing can be borrowed from a reader.
+        assert!(from_reader::<_, Value>(&b"{ 'plain' }"[..]).is_ok());
+    }
+
+    #[test]
+    fn stream() {
+        let text = "Label { name = 'a', address = 1 }\nlocal n = 2\n{ name = 'b', address = n },\nLabel('c', 3);";
+        let mut stream = Deserializer::from_str(text).into_iter::<Label>();
+        let mut records = vec![];
+        while let Some(record) = stream.next() {
+            records.push((stream.byte_offset(), record.unwrap().name));
+        }
+        assert_eq!(records, vec![(0, "a".to_string()), (46, "b".to_string()), (75, "c".to_string())]);
+        assert_eq!(stream.byte_offset(), text.len());
+    }
+
+    #[test]
+    fn stream_recovery() {
+        let text = "{ name = 'a', address = 1 }\n{ name = 'b', address = -1 }\n{ name = 'c' x }\n\
+                    Label { name = 'd', address = 4 }\n@\n{ name = 'e', address = 5 }";
+        let results: Vec<_> = Deserializer::from_str(text).into_iter::<Label>()
+            .map(|record| record.map(|label| label.name))
+            .collect();
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[0], Ok("a".to_string()));
+        assert!(results[1].is_err());
+        assert!(results[2].is_err());
+        assert_eq!(results[3], Ok("d".to_string()));
+        assert!(results[4].is_err());
+        assert_eq!(results[5], Ok("e".to_string()));
+    }
+
+    #[test]
+    fn stream_from_reader() {
+        let text = "{ 1 }\n{ 2, 3 }\n{ 'x' }\n{}";
+        let reader = Trickle { data: text.as_bytes(), chunk: 2 };
+        let results: Vec<_> = Deserializer::from_reader(reader).into_iter::<Vec<i64>>().collect();
+        assert_eq!(results[0], Ok(vec![1]));
+        assert_eq!(results[1], Ok(vec![2, 3]));
+        assert!(results[2].is_err());
+        assert_eq!(results[3], Ok(vec![]));
+
+        // Errors reading the input end the stream.
+        let reader = Trickle { data: b"{ 1 }\n{ '\xff' }\n{ 2 }", chunk: 64 };
+        let results: Vec<_> = Deserializer::from_reader(reader).into_iter::<Vec<i64>>().collect();
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[1], Err(Error::InvalidUtf8 { .. })));
+    }
+
+    #[test]
+    fn negative_numbers() {
+        assert_eq!(from_str::<i64>("-0x10").unwrap(), -16);
+        assert_eq!(from_str::<i64>("-0x8000000000000000").unwrap(), i64::MIN);
+        assert_eq!(from_str::<Vec<i64>>("{ -0x7f, - -2, -3 }").unwrap(), vec![-127, 2, -3]);
+        // Past `i128`, integers are read as floats, keeping their sign.
+        let huge = "-170141183460469231731687303715884105729";
+        assert_eq!(from_str::<f64>(huge).unwrap(), -1.7014118346046923e38);
+        assert_eq!(from_str::<Value>(&format!("- {}", huge)).unwrap(), Value::Float(1.7014118346046923e38));
+        assert_eq!(from_str::<f64>(&format!("1/{}", huge)).unwrap(), -1.0 / 1.7014118346046923e38);
+    }
+
+    #[test]
+    fn quotients() {
+        assert!(from_str::<f64>("0/0").unwrap().is_nan());
+        assert_eq!(from_str::<f64>("1/0").unwrap(), f64::INFINITY);
+        assert_eq!(from_str::<Vec<f64>>("{ -1/0, 3/2, 1.5/0.5 }").unwrap(), vec![f64::NEG_INFINITY, 1.5, 3.0]);
+        let error = from_str::<f64>("1/x").unwrap_err();
+        assert_eq!(error.to_string(), "unexpected name 'x'; expected a number at line 1, column 3");
+        assert!(check("{ 0/0, -1/0 }").is_empty());
+        assert_eq!(check("{ 1/ }").len(), 1);
+    }
+
+    #[test]
+    fn integer_keys() {
+        use std::collections::{BTreeMap, HashMap};
+
+        let map: HashMap<u32, String> = from_str("{ [42] = \"a\", [7] = \"b\" }").unwrap();
+        assert_eq!(map, vec![(42, "a".to_string()), (7, "b".to_string())].into_iter().collect());
+        let map: BTreeMap<i64, u8> = from_str("{ [-1] = 1, [1.0] = 2, [0x10] = 3 }").unwrap();
+        assert_eq!(map, vec![(-1, 1), (1, 2), (16, 3)].into_iter().collect());
+        let map: BTreeMap<u8, u8> = from_str("{ 10, 20, [5] = 50 }").unwrap();
+        assert_eq!(map, vec![(1, 10), (2, 20), (5, 50)].into_iter().collect());
+        assert!(from_str::<BTreeMap<u8, u8>>("{ [1.5] = 1 }").is_err());
+        assert!(from_str::<BTreeMap<u8, u8>>("{ [300] = 1 }").is_err());
+    }
+
+    #[test]
+    fn other_keys() {
+        use std::collections::{BTreeMap, HashMap};
+
+        #[derive(Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        struct Id(u32);
+
+        #[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+        enum Side {
+            Left,
+            Right
+        }
+
+        let map: HashMap<bool, u8> = from_str("{ [true] = 1, [false] = 0 }").unwrap();
+        assert_eq!(map, vec![(true, 1), (false, 0)].into_iter().collect());
+        let map: BTreeMap<Id, u8> = from_str("{ [3] = 1 }").unwrap();
+        assert_eq!(map, vec![(Id(3), 1)].into_iter().collect());
+        let map: BTreeMap<Side, u8> = from_str("{ Left = 1, [\"Right\"] = 2 }").unwrap();
+        assert_eq!(map, vec![(Side::Left, 1), (Side::Right, 2)].into_iter().collect());
+        let map: BTreeMap<String, u8> = from_str("{ [\"x y\"] = 1, z = 2 }").unwrap();
+        assert_eq!(map, vec![("x y".to_string(), 1), ("z".to_string(), 2)].into_iter().collect());
+        let error = from_str::<BTreeMap<String, u8>>("{ [0/0] = 1 }").unwrap_err();
+        assert_eq!(error.to_string(), "table index is NaN at line 1, column 4");
+        assert!(from_str::<BTreeMap<String, u8>>("{ [nil] = 1 }").is_err());
+    }
+
+    fn limited<T: Deserialize<'static>>(text: &'static str, limits: Limits) -> Result<T> {
+        let mut deserializer = Deserializer::from_str(text);
+        deserializer.set_limits(limits);
+        let value = T::deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        Ok(value)
+    }
+
+    // The limit `result` went past, and where.
+    fn exceeded<T: std::fmt::Debug>(result: Result<T>) -> (Limit, usize, usize) {
+        match result {
+            Err(Error::LimitExceeded { limit, max, position }) => (limit, max, position.offset),
+            result => panic!("expected a limit error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn depth_limit() {
+        let limits = Limits { depth: 2, ..Limits::default() };
+        assert_eq!(limited::<Value>("{ { 1 } }", limits).unwrap(), from_str::<Value>("{ { 1 } }").unwrap());
+        assert_eq!(exceeded(limited::<Value>("{ { { 1 } } }", limits)), (Limit::Depth, 2, 4));
+        assert_eq!(exceeded(limited::<Vec<Vec<Vec<u8>>>>("{ { { 1 } } }", limits)), (Limit::Depth, 2, 4));
+    }
+
+    #[test]
+    fn default_depth_limit() {
+        let text = "{".repeat(100_000);
+        let error = from_str::<Value>(&text).unwrap_err();
+        assert_eq!(exceeded::<()>(Err(error)), (Limit::Depth, 128, 128));
+        let text = format!("return {}1", "- ".repeat(100_001));
+        assert_eq!(from_str::<i64>(&text).unwrap(), -1);
+    }
+
+    #[test]
+    fn size_limits() {
+        let limits = Limits { input_size: 10, ..Limits::default() };
+        assert_eq!(limited::<Vec<u8>>("{ 1, 2 }", limits).unwrap(), vec![1, 2]);
+        assert_eq!(exceeded(limited::<Vec<u8>>("{ 1, 2, 3, 4 }", limits)).0, Limit::InputSize);
+        let mut deserializer = Deserializer::from_reader("{ 1, 2, 3, 4 }".as_bytes());
+        deserializer.set_limits(limits);
+        assert_eq!(exceeded(Vec::<u8>::deserialize(&mut deserializer)).0, Limit::InputSize);
+
+        let limits = Limits { string_length: 3, ..Limits::default() };
+        assert_eq!(limited::<String>("\"\\65\\66\\67\"", limits).unwrap(), "ABC");
+        assert_eq!(exceeded(limited::<Vec<String>>("{ \"abc\", \"abcd\" }", limits)), (Limit::StringLength, 3, 9));
+    }
+
+    #[test]
+    fn table_entry_limit() {
+        let limits = Limits { table_entries: 2, ..Limits::default() };
+        assert_eq!(limited::<Vec<Vec<u8>>>("{ { 1, 2 }, { 3 } }", limits).unwrap(), vec![vec![1, 2], vec![3]]);
+        assert_eq!(exceeded(limited::<Vec<u8>>("{ 1, 2, 3 }", limits)), (Limit::TableEntries, 2, 8));
+        assert_eq!(exceeded(limited::<Value>("{ a = 1, b = 2, c = 3 }", limits)).0, Limit::TableEntries);
+        assert_eq!(exceeded(limited::<Colors>("{ fg = { 1 }, bg = { 2 }, x = 3 }", limits)).0, Limit::TableEntries);
+    }
+
+    #[test]
+    fn evaluation_step_limit() {
+        let limits = Limits { evaluation_steps: 3, ..Limits::default() };
+        assert_eq!(limited::<Vec<i64>>("local a = 1 return { a, -a, 2 }", limits).unwrap(), vec![1, -1, 2]);
+        assert_eq!(exceeded(limited::<Vec<i64>>("local a = 1 return { a, a, -a }", limits)), (Limit::EvaluationSteps, 3, 27));
+    }
+
+    // Reads `text` as a `T`, returning the warnings with where they were
+    // raised.
+    fn checked<T: Deserialize<'static>>(text: &'static str, duplicate_keys: DuplicateKeys) -> Result<(T, Vec<(String, usize)>)> {
+        let mut deserializer = Deserializer::from_str(text);
+        deserializer.set_duplicate_keys(duplicate_keys);
+        let value = T::deserialize(&mut deserializer)?;
+        deserializer.end()?;
+        let warnings = deserializer.warnings().iter()
+            .map(|warning| (warning.message.clone(), warning.position.offset))
+            .collect();
+        Ok((value, warnings))
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        let text = "{ a = 1, b = 2, a = 3 }";
+        let (value, warnings) = checked::<BTreeMap<String, u8>>(text, DuplicateKeys::Allow).unwrap();
+        assert_eq!(value["a"], 3);
+        assert!(warnings.is_empty());
+        let (value, warnings) = checked::<BTreeMap<String, u8>>(text, DuplicateKeys::Warn).unwrap();
+        assert_eq!(value["a"], 3);
+        assert_eq!(warnings, [("duplicate key [\"a\"] in table".to_string(), 16)]);
+        let error = checked::<BTreeMap<String, u8>>(text, DuplicateKeys::Error).unwrap_err();
+        assert_eq!(error.to_string(), "duplicate key [\"a\"] in table at line 1, column 17");
+
+        // `Value`s are built by a different path than maps.
+        let (value, warnings) = checked::<Value>(text, DuplicateKeys::Warn).unwrap();
+        assert_eq!(value, from_str::<Value>("{ b = 2, a = 3 }").unwrap());
+        assert_eq!(warnings.len(), 1);
+        let error = checked::<Value>(text, DuplicateKeys::Error).unwrap_err();
+        assert_eq!(error.position().map(|position| position.offset), Some(16));
+        assert!(checked::<Value>("{ a = 1, ['a'] = 2 }", DuplicateKeys::Error).is_err());
+    }
+
+    #[test]
+    fn duplicate_positional_keys() {
+        let text = "{ \"x\", [1] = \"y\" }";
+        let (value, _) = checked::<BTreeMap<i64, String>>(text, DuplicateKeys::Allow).unwrap();
+        assert_eq!(value[&1], "y");
+        let error = checked::<BTreeMap<i64, String>>(text, DuplicateKeys::Error).unwrap_err();
+        assert_eq!(error.to_string(), "duplicate key [1] in table at line 1, column 8");
+        let error = checked::<Value>(text, DuplicateKeys::Error).unwrap_err();
+        assert_eq!(error.to_string(), "duplicate key [1] in table at line 1, column 8");
+        assert!(checked::<Value>("{ [1] = \"y\", \"x\" }", DuplicateKeys::Error).is_err());
+        assert!(checked::<Value>("{ [2] = \"y\", \"x\" }", DuplicateKeys::Error).is_ok());
+        assert!(checked::<Value>("{ [1] = 1, [1.0] = 2 }", DuplicateKeys::Error).is_err());
+    }
+
+    #[test]
+    fn warnings() {
+        let (value, warnings) = checked::<Colors>("{ fg = { 1 }, bg = { 2 }, alpha = 3 }", DuplicateKeys::Allow).unwrap();
+        assert_eq!(value, Colors { fg: vec![1], bg: vec![2] });
+        assert_eq!(warnings, [("unknown field 'alpha'".to_string(), 26)]);
+
+        let mixed = "table mixes positional items with keys".to_string();
+        let (_, warnings) = checked::<Value>("{ 1, 2, a = 3, b = 4 }", DuplicateKeys::Allow).unwrap();
+        assert_eq!(warnings, [(mixed.clone(), 8)]);
+        let (_, warnings) = checked::<BTreeMap<i64, u8>>("{ 1, [5] = 3 }", DuplicateKeys::Allow).unwrap();
+        assert_eq!(warnings, [(mixed, 5)]);
+        let (_, warnings) = checked::<Value>("{ { 1, 2 }, { a = 1 }, [3] = { } }", DuplicateKeys::Allow).unwrap();
+        assert_eq!(warnings.len(), 1);
+
+        // Warnings also carry a line and column, and the included file they
+        // were raised in, if any.
+        let mut deserializer = Deserializer::from_str("{ fg = { 1 }, bg = { 2 }, alpha = 3 }");
+        Colors::deserialize(&mut deserializer).unwrap();
+        deserializer.end().unwrap();
+        assert_eq!(deserializer.warnings()[0].file, None);
+        assert_eq!(deserializer.warnings()[0].position.column, 27);
+    }
+
+    fn check_messages(text: &str) -> Vec<String> {
+        check(text).iter().map(Error::to_string).collect()
+    }
+
+    #[test]
+    fn check_valid_input() {
+        assert!(check("local a <const> = 1\nreturn { a, f(x), data:get 'y', [a] = -a }").is_empty());
+        assert!(check("Item { 1 }; Item { 2 }, list[#list+1] = { 3 }").is_empty());
+        assert!(check("do local a = 1 end return 1").is_empty());
+        assert!(check("").is_empty());
+    }
+
+    #[test]
+    fn check_recovers_in_tables() {
+        assert_eq!(check_messages("{ a = , b = 2, c = }"), [
+            "unexpected ','; expected a value at line 1, column 7",
+            "unexpected '}'; expected a value at line 1, column 20"
+        ]);
+        assert_eq!(check_messages("{ 1 2, 3; = 4 }"), [
+            "unexpected number; expected ',' or '}' at line 1, column 5",
+            "unexpected '='; expected a value at line 1, column 11"
+        ]);
+        assert_eq!(check_messages("{ a = { 1, , 2 }, b = ) }"), [
+            "unexpected ','; expected a value at line 1, column 12",
+            "unexpected ')'; expected a value at line 1, column 23"
+        ]);
+        assert_eq!(check_messages("{ [ = 1, b = }"), [
+            "unexpected '='; expected a value at line 1, column 5",
+            "unexpected '}'; expected a value at line 1, column 14"
+        ]);
+        assert_eq!(check_messages("{ a = 1"), ["unexpected end of input; expected ',' or '}' at line 1, column 8"]);
+    }
+
+    #[test]
+    fn check_recovers_at_statements() {
+        assert_eq!(check_messages("Item { name = 'a' = }\nItem { 1, 2 }\nItem { x = }"), [
+            "unexpected '='; expected ',' or '}' at line 1, column 19",
+            "unexpected '}'; expected a value at line 3, column 12"
+        ]);
+        assert_eq!(check_messages("local a = \nlocal b <close> = 2\nreturn { a, }"), [
+            "unexpected 'local'; expected a value at line 2, column 1",
+            "unsupported attribute 'close' at line 2, column 10"
+        ]);
+        assert_eq!(check_messages("f(1, , 2) g{ = }\ndata:extend(1 2)"), [
+            "unexpected ','; expected a value at line 1, column 6",
+            "unexpected '='; expected a value at line 1, column 14",
+            "unexpected number; expected ')' at line 2, column 15"
+        ]);
+        assert_eq!(check_messages("do return { 1 } "), ["unexpected end of input; expected 'end' at line 1, column 17"]);
+        assert_eq!(check_messages("return 1 2"), ["unexpected number; expected end of input at line 1, column 10"]);
+    }
+
+    #[test]
+    fn check_stops_at_read_errors() {
+        assert_eq!(check_messages("{ a = , \"unterminated }"), [
+            "unexpected ','; expected a value at line 1, column 7",
+            "unfinished string or invalid escape sequence at line 1, column 9"
+        ]);
+        let errors = Deserializer::from_str("{ = }, { = }").check();
+        assert_eq!(errors.iter().map(|e| e.position().unwrap().offset).collect::<Vec<_>>(), [2, 9]);
+    }
+
+    #[test]
+    fn keyed_list_order() {
+        assert_eq!(from_str::<Vec<String>>("{ [1] = \"a\", [2] = \"b\" }").unwrap(), ["a", "b"]);
+        let error = from_str::<Vec<String>>("{ [2] = \"b\", [1] = \"a\" }").unwrap_err();
+        assert_eq!(error.to_string(), "expected the key [1] for the next list item at line 1, column 4");
+        let error = from_str::<Vec<String>>("{ [1] = \"a\", [3] = \"c\" }").unwrap_err();
+        assert_eq!(error.to_string(), "expected the key [2] for the next list item at line 1, column 15");
+        let map = from_str::<BTreeMap<i64, String>>("{ [2] = \"b\", [1] = \"a\" }").unwrap();
+        assert_eq!(map.into_values().collect::<Vec<_>>(), ["a", "b"]);
+    }
+}
@@ -1,4 +1,3 @@
-
 use std;
 use std::fmt::{self, Display};
 
@@ -6,9 +5,68 @@ use serde::{de, ser};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// A location in LSON source. `line` and `column` start at 1; `column` counts
+// characters, not bytes.
+#[derive(Debug,Eq,PartialEq,Ord,PartialOrd,Hash,Clone,Copy)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+// The resource limits of `de::Limits`.
+#[derive(Debug,Eq,PartialEq,Ord,PartialOrd,Hash,Clone,Copy)]
+pub enum Limit {
+    Depth,
+    InputSize,
+    StringLength,
+    TableEntries,
+    EvaluationSteps
+}
+
+// A problem with the input that doesn't stop it from being read, such as a
+// field the target type doesn't have. `file` is the included file it's in,
+// if it isn't in the input itself.
+#[derive(Debug,Eq,PartialEq,Ord,PartialOrd,Hash,Clone)]
+pub struct Warning {
+    pub message: String,
+    pub position: Position,
+    pub file: Option<String>
+}
+
 #[derive(Debug,Eq,PartialEq,Ord,PartialOrd,Hash,Clone)]
 pub enum Error {
     Message(String),
+    Syntax {
+        message: String,
+        position: Position
+    },
+    Data {
+        message: String,
+        position: Position
+    },
+    UnresolvedName {
+        name: String,
+        position: Position
+    },
+    UnknownFunction {
+        name: String,
+        position: Position
+    },
+    // An error inside a file included with `require` or `dofile`. `position`
+    // is the include in the including source; `error` is positioned within
+    // `file`.
+    Include {
+        file: String,
+        position: Position,
+        error: Box<Error>
+    },
+    // The input went past one of the deserializer's limits, which is `max`.
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+        position: Position
+    },
     InvalidUtf8 {
         valid_up_to: usize,
         error_len: Option<usize>
@@ -16,6 +74,29 @@ pub enum Error {
     IoError(String)
 }
 
+impl Error {
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Error::Syntax { position, .. }
+                | Error::Data { position, .. }
+                | Error::UnresolvedName { position, .. }
+                | Error::UnknownFunction { position, .. }
+                | Error::Include { position, .. }
+                | Error::LimitExceeded { position, .. } => Some(*position),
+            _ => None
+        }
+    }
+
+    // Attaches a position to an error raised without one, such as the
+    // errors produced by `Deserialize` implementations.
+    pub fn at(self, position: Position) -> Error {
+        match self {
+            Error::Message(message) => Error::Data { message, position },
+            error => error
+        }
+    }
+}
+
 impl ser::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
         Error::Message(msg.to_string())
@@ -28,10 +109,47 @@ impl de::Error for Error {
     }
 }
 
+impl Display for Position {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl Display for Limit {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            Limit::Depth => "nesting depth",
+            Limit::InputSize => "input size",
+            Limit::StringLength => "string length",
+            Limit::TableEntries => "table entry count",
+            Limit::EvaluationSteps => "evaluation steps"
+        })
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(formatter, "{} at {} in '{}'", self.message, self.position, file),
+            None => write!(formatter, "{} at {}", self.message, self.position)
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Message(msg) => formatter.write_str(msg),
+            Error::Syntax { message, position } | Error::Data { message, position } =>
+                write!(formatter, "{} at {}", message, position),
+            Error::UnresolvedName { name, position } =>
+                write!(formatter, "unresolved name '{}' at {}", name, position),
+            Error::UnknownFunction { name, position } =>
+                write!(formatter, "call to unregistered function '{}' at {}", name, position),
+            Error::Include { file, position, error } =>
+                write!(formatter, "{} in '{}', included at {}", error, file, position),
+            Error::LimitExceeded { limit, max, position } =>
+                write!(formatter, "{} limit of {} exceeded at {}", limit, max, position),
             Error::InvalidUtf8 { valid_up_to, error_len } => formatter.write_str(
                 &format!("Invalid UTF-8; valid up to: {}, error length: {}",
                          valid_up_to,
@@ -0,0 +1,556 @@
+
+use std::borrow::Cow;
+use std::io::Read;
+
+use crate::error::{Error, Result};
+use crate::parser::{Token, Tokens};
+
+pub use crate::parser::Span;
+
+// A pull parser that reports the structure of LSON as it is read, without
+// evaluating it. Nothing but the open tables and calls is kept in memory.
+//
+// The input may start with `return`, and is followed by any number of
+// values, so a file of records reads as a sequence of values. Names and
+// calls are reported as they appear; locals are reported with `Local`
+// before their value, but only one may be declared at a time. `do ... end`
+// blocks are reported so that locals declared inside can be dropped at the
+// end. Like the input, a block may return its value with `return`.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Event<'s> {
+    TableStart,
+    // The key of the value that follows, inside a table.
+    Key(KeyKind<'s>),
+    Scalar(Scalar<'s>),
+    TableEnd,
+    // A reference to a local or a symbol, such as `RED` or `Align.Center`.
+    Name(Cow<'s, str>),
+    // The start of a call like `rgb(1, 2, 3)` or `Entry { ... }`. The
+    // arguments follow as values, then `CallEnd`.
+    CallStart(Cow<'s, str>),
+    CallEnd,
+    // `local name =`, followed by its value.
+    Local(Cow<'s, str>),
+    // `do`
+    BlockStart,
+    // `end`
+    BlockEnd
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum KeyKind<'s> {
+    // `name = value`
+    Field(Cow<'s, str>),
+    // `[key] = value`
+    Bracketed(Scalar<'s>),
+    // `[expr] = value` for any key but a literal, such as `[Align.Center]`.
+    // The events of the key follow, then those of the value.
+    Expression,
+    // A positional item, counting from 1.
+    Positional(usize)
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Scalar<'s> {
+    Nil,
+    Boolean(bool),
+    Integer(i128),
+    Float(f64),
+    String(Cow<'s, str>)
+}
+
+enum Frame {
+    Table { positional: usize },
+    // `parenthesized` is false for calls like `f { ... }` and `f "..."`,
+    // which take a single argument.
+    Call { parenthesized: bool },
+    // The expression of an `[expr] =` key.
+    Key
+}
+
+#[derive(Clone,Copy,PartialEq)]
+enum State {
+    Start,
+    TopLevel,
+    Value,
+    AfterValue,
+    TableEntry,
+    FirstArgument,
+    Done
+}
+
+pub struct EventReader<'s> {
+    tokens: Tokens<'s>,
+    stack: Vec<Frame>,
+    // How many `do` blocks are open.
+    blocks: usize,
+    state: State,
+    // The span of the last event.
+    last: Option<Span>
+}
+
+impl<'s> EventReader<'s> {
+    pub fn new(text: &'s str) -> EventReader<'s> {
+        EventReader::with_tokens(Tokens::new(text))
+    }
+
+    // Reads the input in pieces as events are pulled.
+    pub fn from_reader<R: Read + 's>(reader: R) -> EventReader<'s> {
+        EventReader::with_tokens(Tokens::from_reader(reader))
+    }
+
+    fn with_tokens(tokens: Tokens<'s>) -> EventReader<'s> {
+        EventReader { tokens, stack: vec![], blocks: 0, state: State::Start, last: None }
+    }
+
+    // How many tables, calls and `[expr]` keys are open.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    fn step(&mut self) -> Result<Option<(Event<'s>, Span)>> {
+        loop {
+            match self.state {
+                State::Start => {
+                    if self.tokens.peek() == Some(&Token::Return) {
+                        self.tokens.next();
+                    }
+                    self.state = State::TopLevel;
+                },
+                State::TopLevel => match self.tokens.peek() {
+                    None if self.blocks > 0 => return Err(self.unexpected("'end'")),
+                    None => {
+                        self.state = State::Done;
+                        return Ok(None);
+                    },
+                    Some(Token::Semicolon) => {
+                        self.tokens.next();
+                    },
+                    Some(Token::Local) => return self.local().map(Some),
+                    Some(Token::Do) => {
+                        let (_, span) = self.tokens.next().unwrap();
+                        self.blocks += 1;
+                        return Ok(Some((Event::BlockStart, span)));
+                    },
+                    Some(Token::Return) if self.blocks > 0 => {
+                        self.tokens.next();
+                    },
+                    Some(Token::End) if self.blocks > 0 => {
+                        let (_, span) = self.tokens.next().unwrap();
+                        self.blocks -= 1;
+                        return Ok(Some((Event::BlockEnd, span)));
+                    },
+                    Some(_) => self.state = State::Value
+                },
+                State::Value => return self.value().map(Some),
+                State::AfterValue => match self.stack.last() {
+                    None => {
+                        if let Some(Token::Comma) | Some(Token::Semicolon) = self.tokens.peek() {
+                            self.tokens.next();
+                        }
+                        self.state = State::TopLevel;
+                    },
+                    Some(Frame::Table { .. }) => match self.tokens.peek() {
+                        Some(Token::Comma) | Some(Token::Semicolon) => {
+                            self.tokens.next();
+                            self.state = State::TableEntry;
+                        },
+                        Some(Token::RBrace) => self.state = State::TableEntry,
+                        _ => return Err(self.unexpected("',' or '}'"))
+                    },
+                    Some(Frame::Call { parenthesized: true }) => match self.tokens.peek() {
+                        Some(Token::Comma) => {
+                            self.tokens.next();
+                            self.state = State::Value;
+                        },
+                        Some(Token::RParen) => {
+                            let (_, span) = self.tokens.next().unwrap();
+                            self.stack.pop();
+                            return Ok(Some((Event::CallEnd, span)));
+                        },
+                        _ => return Err(self.unexpected("',' or ')'"))
+                    },
+                    // Ends with the table or string argument.
+                    Some(Frame::Call { parenthesized: false }) => {
+                        self.stack.pop();
+                        return Ok(Some((Event::CallEnd, self.last.unwrap())));
+                    },
+                    Some(Frame::Key) => {
+                        self.expect(Token::RBracket, "']'")?;
+                        self.expect(Token::Assign, "'='")?;
+                        self.stack.pop();
+                        self.state = State::Value;
+                    }
+                },
+                State::TableEntry => return self.table_entry().map(Some),
+                State::FirstArgument => {
+                    if self.tokens.peek() == Some(&Token::RParen) {
+                        let (_, span) = self.tokens.next().unwrap();
+                        self.stack.pop();
+                        self.state = State::AfterValue;
+                        return Ok(Some((Event::CallEnd, span)));
+                    }
+                    self.state = State::Value;
+                },
+                State::Done => return Ok(None)
+            }
+        }
+    }
+
+    // local name [<const>] =
+    fn local(&mut self) -> Result<(Event<'s>, Span)> {
+        let (_, start) = self.tokens.next().unwrap();
+        let (name, mut span) = self.expect_name()?;
+        if self.tokens.peek() == Some(&Token::Less) {
+            self.tokens.next();
+            let (attribute, attribute_span) = self.expect_name()?;
+            if attribute != "const" {
+                return Err(Error::Syntax {
+                    message: format!("unsupported attribute '{}'", attribute),
+                    position: attribute_span.start
+                });
+            }
+            span = self.expect(Token::Greater, "'>'")?;
+        }
+        if self.tokens.peek() == Some(&Token::Comma) {
+            let span = self.tokens.peek_span();
+            return Err(Error::Syntax {
+                message: "declaring several locals at once is not supported".to_string(),
+                position: span.start
+            });
+        }
+        self.expect(Token::Assign, "'='")?;
+        self.state = State::Value;
+        Ok((Event::Local(name), Span { start: start.start, end: span.end }))
+    }
+
+    fn value(&mut self) -> Result<(Event<'s>, Span)> {
+        if let Some((scalar, span)) = self.scalar()? {
+            self.state = State::AfterValue;
+            return Ok((Event::Scalar(scalar), span));
+        }
+        match self.tokens.peek() {
+            Some(Token::LBrace) => {
+                let (_, span) = self.tokens.next().unwrap();
+                self.stack.push(Frame::Table { positional: 0 });
+                self.state = State::TableEntry;
+                Ok((Event::TableStart, span))
+            },
+            Some(Token::Name(_)) => self.name(),
+            _ => Err(self.unexpected("a value"))
+        }
+    }
+
+    // A name, a dotted name or a method name, and whether it's called.
+    fn name(&mut self) -> Result<(Event<'s>, Span)> {
+        let (mut name, mut span) = self.expect_name()?;
+        let start = span.start;
+        while let Some(Token::Dot) | Some(Token::Colon) = self.tokens.peek() {
+            let (separator, _) = self.tokens.next().unwrap();
+            let (field, field_span) = self.expect_name()?;
+            let name = name.to_mut();
+            name.push_str(if separator == Token::Dot { "." } else { ":" });
+            name.push_str(&field);
+            span = field_span;
+            if separator == Token::Colon {
+                break;
+            }
+        }
+        let span = Span { start, end: span.end };
+        let parenthesized = match self.tokens.peek() {
+            Some(Token::LParen) => true,
+            Some(Token::LBrace) | Some(Token::Str(_)) => false,
+            _ if name.contains(':') => return Err(self.unexpected("function arguments")),
+            _ => {
+                self.state = State::AfterValue;
+                return Ok((Event::Name(name), span));
+            }
+        };
+        self.stack.push(Frame::Call { parenthesized });
+        if parenthesized {
+            self.tokens.next();
+            self.state = State::FirstArgument;
+        } else {
+            self.state = State::Value;
+        }
+        Ok((Event::CallStart(name), span))
+    }
+
+    fn table_entry(&mut self) -> Result<(Event<'s>, Span)> {
+        if let Some(name) = self.tokens.peek_field_name() {
+            let (_, span) = self.tokens.next().unwrap();
+            self.tokens.next();
+            self.state = State::Value;
+            return Ok((Event::Key(KeyKind::Field(name)), span));
+        }
+        match self.tokens.peek() {
+            Some(Token::RBrace) => {
+                let (_, span) = self.tokens.next().unwrap();
+                self.stack.pop();
+                self.state = State::AfterValue;
+                Ok((Event::TableEnd, span))
+            },
+            Some(Token::LBracket) => {
+                let (_, start) = self.tokens.next().unwrap();
+                let key = match self.scalar()? {
+                    Some((Scalar::Nil, span)) => return Err(Error::Syntax {
+                        message: "table index is nil".to_string(),
+                        position: span.start
+                    }),
+                    Some((key, _)) => key,
+                    None => {
+                        self.stack.push(Frame::Key);
+                        self.state = State::Value;
+                        return Ok((Event::Key(KeyKind::Expression), start));
+                    }
+                };
+                let end = self.expect(Token::RBracket, "']'")?;
+                self.expect(Token::Assign, "'='")?;
+                self.state = State::Value;
+                Ok((Event::Key(KeyKind::Bracketed(key)), Span { start: start.start, end: end.end }))
+            },
+            _ => {
+                let span = self.tokens.peek_span();
+                let position = match self.stack.last_mut() {
+                    Some(Frame::Table { positional }) => {
+                        *positional += 1;
+                        *positional
+                    },
+                    _ => unreachable!()
+                };
+                self.state = State::Value;
+                Ok((Event::Key(KeyKind::Positional(position)), Span { start: span.start, end: span.start.offset }))
+            }
+        }
+    }
+
+    // Reads a literal, possibly negated, if one is next.
+    fn scalar(&mut self) -> Result<Option<(Scalar<'s>, Span)>> {
+        let start = self.tokens.peek_span();
+        let mut signs = 0;
+        while self.tokens.peek() == Some(&Token::Minus) {
+            self.tokens.next();
+            signs += 1;
+        }
+        let negative = signs % 2 == 1;
+        let literal = matches!(self.tokens.peek(),
+            Some(Token::Nil) | Some(Token::Bool(_)) | Some(Token::Integer(_))
+                | Some(Token::Float(_)) | Some(Token::Str(_)));
+        if !literal {
+            return match signs {
+                0 => Ok(None),
+                _ => Err(self.unexpected("a number"))
+            };
+        }
+        let (token, span) = self.tokens.next().unwrap();
+        let span = Span { start: start.start, end: span.end };
+        let scalar = match token {
+            Token::Nil => Scalar::Nil,
+            Token::Bool(b) => Scalar::Boolean(b),
+            Token::Integer(i) => match i.to_i128() {
+                Some(i) if negative => i.checked_neg().map(Scalar::Integer).unwrap_or(Scalar::Float(-(i as f64))),
+                Some(i) => Scalar::Integer(i),
+                None if negative => Scalar::Float(-i.to_f64()),
+                None => Scalar::Float(i.to_f64())
+            },
+            Token::Float(f) if negative => Scalar::Float(-f),
+            Token::Float(f) => Scalar::Float(f),
+            Token::Str(s) => Scalar::String(s),
+            _ => unreachable!()
+        };
+        if signs > 0 && !matches!(scalar, Scalar::Integer(_) | Scalar::Float(_)) {
+            return Err(Error::Syntax { message: "attempt to negate a non-number".to_string(), position: span.start });
+        }
+        if self.tokens.peek() == Some(&Token::Slash) {
+            return self.quotient(scalar, span).map(Some);
+        }
+        Ok(Some((scalar, span)))
+    }
+
+    // `0/0`, `1/0` and `-1/0`, the way NaN and the infinities are written.
+    fn quotient(&mut self, dividend: Scalar<'s>, span: Span) -> Result<(Scalar<'s>, Span)> {
+        let dividend = match dividend {
+            Scalar::Integer(i) => i as f64,
+            Scalar::Float(f) => f,
+            _ => return Err(Error::Syntax {
+                message: "attempt to perform arithmetic on a non-number".to_string(),
+                position: span.start
+            })
+        };
+        self.tokens.next();
+        let divisor = match self.tokens.peek() {
+            Some(Token::Integer(i)) => i.to_f64(),
+            Some(Token::Float(f)) => *f,
+            _ => return Err(self.unexpected("a number"))
+        };
+        let (_, end) = self.tokens.next().unwrap();
+        Ok((Scalar::Float(dividend / divisor), Span { start: span.start, end: end.end }))
+    }
+
+    fn expect(&mut self, expected: Token<'s>, description: &str) -> Result<Span> {
+        if self.tokens.peek() == Some(&expected) {
+            Ok(self.tokens.next().unwrap().1)
+        } else {
+            Err(self.unexpected(description))
+        }
+    }
+
+    fn expect_name(&mut self) -> Result<(Cow<'s, str>, Span)> {
+        if let Some(Token::Name(_)) = self.tokens.peek() {
+            if let Some((Token::Name(name), span)) = self.tokens.next() {
+                return Ok((name, span));
+            }
+        }
+        Err(self.unexpected("a name"))
+    }
+
+    fn unexpected(&mut self, expected: &str) -> Error {
+        let span = self.tokens.peek_span();
+        let message = match self.tokens.peek() {
+            Some(Token::Error) => match self.tokens.read_error() {
+                Some(error) => return error.clone(),
+                None => self.tokens.describe_error(&span)
+            },
+            Some(token) => format!("unexpected {}; expected {}", token, expected),
+            None => format!("unexpected end of input; expected {}", expected)
+        };
+        Error::Syntax { message, position: span.start }
+    }
+}
+
+// Yields events until the end of the input or the first error.
+impl<'s> Iterator for EventReader<'s> {
+    type Item = Result<(Event<'s>, Span)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let event = self.step();
+        match &event {
+            Ok(Some((_, span))) => self.last = Some(*span),
+            Ok(None) => {},
+            Err(_) => self.state = State::Done
+        }
+        event.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(text: &str) -> Result<Vec<Event<'_>>> {
+        EventReader::new(text).map(|event| event.map(|(event, _)| event)).collect()
+    }
+
+    fn name(name: &str) -> Cow<'_, str> {
+        Cow::Borrowed(name)
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(events("return { 1, x = \"a\", [2.5] = true }").unwrap(), vec![
+            Event::TableStart,
+            Event::Key(KeyKind::Positional(1)),
+            Event::Scalar(Scalar::Integer(1)),
+            Event::Key(KeyKind::Field(name("x"))),
+            Event::Scalar(Scalar::String(name("a"))),
+            Event::Key(KeyKind::Bracketed(Scalar::Float(2.5))),
+            Event::Scalar(Scalar::Boolean(true)),
+            Event::TableEnd
+        ]);
+    }
+
+    #[test]
+    fn calls_and_names() {
+        assert_eq!(events("Entry { rgb(1, -2), Align.Center }").unwrap(), vec![
+            Event::CallStart(name("Entry")),
+            Event::TableStart,
+            Event::Key(KeyKind::Positional(1)),
+            Event::CallStart(name("rgb")),
+            Event::Scalar(Scalar::Integer(1)),
+            Event::Scalar(Scalar::Integer(-2)),
+            Event::CallEnd,
+            Event::Key(KeyKind::Positional(2)),
+            Event::Name(name("Align.Center")),
+            Event::TableEnd,
+            Event::CallEnd
+        ]);
+    }
+
+    #[test]
+    fn expression_keys() {
+        assert_eq!(events("{ [Align.Center] = 1, [RED] = rgb(1), [f{}] = 2 }").unwrap(), vec![
+            Event::TableStart,
+            Event::Key(KeyKind::Expression),
+            Event::Name(name("Align.Center")),
+            Event::Scalar(Scalar::Integer(1)),
+            Event::Key(KeyKind::Expression),
+            Event::Name(name("RED")),
+            Event::CallStart(name("rgb")),
+            Event::Scalar(Scalar::Integer(1)),
+            Event::CallEnd,
+            Event::Key(KeyKind::Expression),
+            Event::CallStart(name("f")),
+            Event::TableStart,
+            Event::TableEnd,
+            Event::CallEnd,
+            Event::Scalar(Scalar::Integer(2)),
+            Event::TableEnd
+        ]);
+        let error = events("{ [RED = 1 }").unwrap_err();
+        assert_eq!(error.to_string(), "unexpected '='; expected ']' at line 1, column 8");
+        assert!(events("{ [nil] = 1 }").is_err());
+    }
+
+    #[test]
+    fn blocks() {
+        assert_eq!(events("local a = 1 do local b = a return b end").unwrap(), vec![
+            Event::Local(name("a")),
+            Event::Scalar(Scalar::Integer(1)),
+            Event::BlockStart,
+            Event::Local(name("b")),
+            Event::Name(name("a")),
+            Event::Name(name("b")),
+            Event::BlockEnd
+        ]);
+        let error = events("do 1").unwrap_err();
+        assert_eq!(error.to_string(), "unexpected end of input; expected 'end' at line 1, column 5");
+        assert!(events("1 end").is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let spans: Vec<(usize, usize)> = EventReader::new("{ x = 10 }")
+            .map(|event| event.map(|(_, span)| (span.start.offset, span.end)).unwrap())
+            .collect();
+        assert_eq!(spans, vec![(0, 1), (2, 3), (6, 8), (9, 10)]);
+    }
+
+    #[test]
+    fn locals() {
+        assert!(events("local a <const> = 1 a").is_ok());
+        assert!(events("local a <close> = 1").is_err());
+        assert!(events("local a, b = 1, 2").is_err());
+    }
+
+    #[test]
+    fn quotients() {
+        let reader = EventReader::new("{ 0/0, -1/0 }");
+        let read: Vec<(Event, Span)> = reader.collect::<Result<_>>().unwrap();
+        match &read[2].0 {
+            Event::Scalar(Scalar::Float(f)) => assert!(f.is_nan()),
+            event => panic!("unexpected {:?}", event)
+        }
+        assert_eq!(read[4].0, Event::Scalar(Scalar::Float(f64::NEG_INFINITY)));
+        assert_eq!((read[4].1.start.offset, read[4].1.end), (7, 11));
+        assert!(events("{ \"a\"/0 }").is_err());
+        assert!(events("{ 1/ }").is_err());
+    }
+
+    #[test]
+    fn large_integers() {
+        let read = events("{ -170141183460469231731687303715884105729, - -170141183460469231731687303715884105729 }").unwrap();
+        assert_eq!(read[2], Event::Scalar(Scalar::Float(-1.7014118346046923e38)));
+        assert_eq!(read[4], Event::Scalar(Scalar::Float(1.7014118346046923e38)));
+        assert_eq!(events("1/-170141183460469231731687303715884105729").unwrap()[0], Event::Scalar(Scalar::Float(-1.0 / 1.7014118346046923e38)));
+    }
+}
@@ -1,18 +1,32 @@
 mod parser;
+pub mod as_pairs;
+pub mod as_set;
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod de;
 pub mod error;
+pub mod events;
+pub mod loader;
 pub mod ser;
-mod type_ser;
+pub mod spanned;
+pub mod symbols;
+pub mod value;
 
-use serde::{de, Deserialize};
-use crate::error::{Error, Result};
+use std::io::Read;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use crate::error::Result;
 
-use logos::{Logos, Lexer};
+pub use crate::spanned::Spanned;
 
-pub fn parse_str<'d, T: Deserialize<'d>>(text: &str) -> Result<T> {
-    let mut lex = parser::Token::lexer(text);
-    let mut string = String::new();
-    while let Some(tok) = lex.next() {
-        string.push_str(&format!(" {:?}", tok));
-    }
-    panic!("Tokens: {}", string)
+pub fn parse_str<'d, T: Deserialize<'d>>(text: &'d str) -> Result<T> {
+    de::from_str(text)
+}
+
+pub fn parse_slice<'d, T: Deserialize<'d>>(bytes: &'d [u8]) -> Result<T> {
+    de::from_slice(bytes)
+}
+
+pub fn parse_reader<R: Read, T: DeserializeOwned>(reader: R) -> Result<T> {
+    de::from_reader(reader)
 }
@@ -0,0 +1,193 @@
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{self, Component, Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+// The text of an included file. `name` identifies the file in errors and in
+// include-cycle detection.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Source {
+    pub name: String,
+    pub text: String
+}
+
+// Resolves `require("x.y")` and `dofile("path")` for the deserializer.
+pub trait Loader {
+    fn require(&self, module: &str) -> Result<Source>;
+
+    fn dofile(&self, path: &str) -> Result<Source>;
+}
+
+// Loads includes from the filesystem, from under a root directory. Paths
+// given to `dofile` are relative to the root, and so are the templates of
+// `path`, a `package.path`-style list separated by `;` where `?` is replaced
+// by the module name with each `.` turned into a directory separator.
+// Absolute paths, and paths that lead out of the root through `..` or
+// symbolic links, can't be opened, so untrusted input can only include the
+// files under the root.
+#[derive(Debug,Clone)]
+pub struct FileLoader {
+    root: PathBuf,
+    path: String
+}
+
+impl FileLoader {
+    pub fn new<P: AsRef<Path>>(root: P, path: &str) -> FileLoader {
+        FileLoader { root: root.as_ref().to_path_buf(), path: path.to_string() }
+    }
+
+    // The file that `path` names under the root.
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let outside = || Error::Message(format!(
+            "cannot open {}: only files under {} may be included", path, self.root.display()
+        ));
+        let mut file = self.root.clone();
+        let mut depth = 0;
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => {
+                    file.push(part);
+                    depth += 1;
+                },
+                Component::CurDir => {},
+                Component::ParentDir if depth > 0 => {
+                    file.pop();
+                    depth -= 1;
+                },
+                _ => return Err(outside())
+            }
+        }
+        // A file that doesn't exist fails to open later on.
+        if let (Ok(root), Ok(target)) = (fs::canonicalize(&self.root), fs::canonicalize(&file)) {
+            if !target.starts_with(root) {
+                return Err(outside());
+            }
+        }
+        Ok(file)
+    }
+}
+
+impl Default for FileLoader {
+    fn default() -> FileLoader {
+        FileLoader::new(".", "?.lua;?/init.lua")
+    }
+}
+
+impl Loader for FileLoader {
+    fn require(&self, module: &str) -> Result<Source> {
+        let name = module.replace('.', path::MAIN_SEPARATOR_STR);
+        let mut message = format!("module '{}' not found:", module);
+        for template in self.path.split(';').filter(|template| !template.is_empty()) {
+            let candidate = template.replace('?', &name);
+            if matches!(self.resolve(&candidate), Ok(file) if file.is_file()) {
+                return self.dofile(&candidate);
+            }
+            message.push_str(&format!("\n\tno file '{}'", candidate));
+        }
+        Err(Error::Message(message))
+    }
+
+    fn dofile(&self, path: &str) -> Result<Source> {
+        match fs::read_to_string(self.resolve(path)?) {
+            Ok(text) => Ok(Source { name: path.to_string(), text }),
+            Err(error) => Err(Error::Message(format!("cannot open {}: {}", path, error)))
+        }
+    }
+}
+
+// Serves includes from memory, mainly for tests.
+#[derive(Debug,Clone,Default)]
+pub struct MemoryLoader {
+    modules: HashMap<String, String>,
+    files: HashMap<String, String>
+}
+
+impl MemoryLoader {
+    pub fn new() -> MemoryLoader {
+        MemoryLoader::default()
+    }
+
+    pub fn add_module(&mut self, module: &str, text: &str) {
+        self.modules.insert(module.to_string(), text.to_string());
+    }
+
+    pub fn add_file(&mut self, path: &str, text: &str) {
+        self.files.insert(path.to_string(), text.to_string());
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn require(&self, module: &str) -> Result<Source> {
+        match self.modules.get(module) {
+            Some(text) => Ok(Source { name: module.to_string(), text: text.clone() }),
+            None => Err(Error::Message(format!("module '{}' not found", module)))
+        }
+    }
+
+    fn dofile(&self, path: &str) -> Result<Source> {
+        match self.files.get(path) {
+            Some(text) => Ok(Source { name: path.to_string(), text: text.clone() }),
+            None => Err(Error::Message(format!("cannot open {}", path)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_loader() {
+        let mut loader = MemoryLoader::new();
+        loader.add_module("a.b", "return 1");
+        loader.add_file("c.lua", "return 2");
+        assert_eq!(loader.require("a.b").unwrap(), Source { name: "a.b".to_string(), text: "return 1".to_string() });
+        assert_eq!(loader.dofile("c.lua").unwrap().text, "return 2");
+        assert!(loader.require("c.lua").is_err());
+        assert!(loader.dofile("a.b").is_err());
+    }
+
+    #[test]
+    fn file_loader() {
+        let root = std::env::temp_dir().join(format!("serde_lson_loader_{}", std::process::id()));
+        fs::create_dir_all(root.join("common").join("theme")).unwrap();
+        fs::write(root.join("common").join("colors.lua"), "return 1").unwrap();
+        fs::write(root.join("common").join("theme").join("init.lua"), "return 2").unwrap();
+
+        let loader = FileLoader::new(&root, "?.lua;?/init.lua");
+        assert_eq!(loader.require("common.colors").unwrap().text, "return 1");
+        assert_eq!(loader.require("common.theme").unwrap().text, "return 2");
+        let error = loader.require("common.missing").unwrap_err();
+        assert!(error.to_string().starts_with("module 'common.missing' not found:"));
+
+        let source = loader.dofile("common/colors.lua").unwrap();
+        assert_eq!(source.name, "common/colors.lua");
+        assert_eq!(loader.dofile("./common/theme/../colors.lua").unwrap().text, "return 1");
+        assert!(loader.dofile("common/missing.lua").unwrap_err().to_string().starts_with("cannot open common/missing.lua: "));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn file_loader_confinement() {
+        let base = std::env::temp_dir().join(format!("serde_lson_confined_{}", std::process::id()));
+        let root = base.join("root");
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::write(base.join("secret.lua"), "return 'secret'").unwrap();
+
+        let loader = FileLoader::new(&root, "?.lua");
+        let outside = format!("only files under {} may be included", root.display());
+        for path in ["../secret.lua", "data/../../secret.lua", base.join("secret.lua").to_str().unwrap()] {
+            let error = loader.dofile(path).unwrap_err();
+            assert_eq!(error.to_string(), format!("cannot open {}: {}", path, outside));
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(base.join("secret.lua"), root.join("data").join("link.lua")).unwrap();
+            assert!(loader.dofile("data/link.lua").unwrap_err().to_string().ends_with(&outside));
+            assert!(loader.require("data.link").is_err());
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -50,7 +50,7 @@ fn main() {
     println!("{}", serde_lson::ser::to_string_pretty(&map).unwrap());
 
 
-    serde_lson::parse_str::<String>("and nil -128 321 elseif true false");
+    let _ = serde_lson::parse_str::<String>("and nil -128 321 elseif true false");
 }
 
 #[derive(Debug,Clone,Deserialize,Serialize,Hash,PartialEq,Eq)]
@@ -1,7 +1,13 @@
 
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
 use std::io::Read;
-use std::iter::Iterator;
+use std::ops::Range;
+use std::str;
 use logos::{Logos, Lexer};
+use crate::error::{Error, Limit, Position};
 
 #[derive(Debug,Eq,PartialEq,Ord,PartialOrd,Hash,Clone)]
 pub struct Int {
@@ -9,11 +15,42 @@ pub struct Int {
     value: u128
 }
 
+impl Int {
+    // Returns `None` when the value doesn't fit in an `i128`: above
+    // `i128::MAX`, or below `i128::MIN` for negative literals.
+    pub fn to_i128(&self) -> Option<i128> {
+        if self.negative {
+            if self.value == 1u128 << 127 {
+                Some(i128::MIN)
+            } else {
+                i128::try_from(self.value).ok().map(|v| -v)
+            }
+        } else {
+            i128::try_from(self.value).ok()
+        }
+    }
+
+    // The nearest float, for values that don't fit in an `i128`.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.value as f64;
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+}
+
 #[derive(Logos, Debug, PartialEq, Clone)]
-pub enum Token {
-    #[regex(r"[ \t\n\r\f]+", logos::skip)]
+pub enum Token<'s> {
+    #[regex(r"[ \t\n\r\f\x0B]+", logos::skip)]
     Whitespace,
 
+    // Comments are produced as tokens so that long comments can be checked
+    // for termination; `Tokens` drops them.
+    #[token("--", comment)]
+    Comment,
+
     #[token("and")]
     And,
 
@@ -65,7 +102,7 @@ pub enum Token {
     #[token("return")]
     Return,
 
-    #[token("then")] 
+    #[token("then")]
     Then,
 
     #[token("until")]
@@ -79,25 +116,824 @@ pub enum Token {
 
     #[regex(r"[0-9]+", pos_decimal)]
     #[regex(r"-[0-9]+", neg_decimal)]
+    #[regex(r"0[xX][0-9a-fA-F]+", hexadecimal)]
+    #[regex(r"-0[xX][0-9a-fA-F]+", neg_hexadecimal)]
     Integer(Int),
 
-    #[regex("-?[0-9]+\\.[0-9]+", |lex| lex.slice().parse())]
+    #[regex(r"-?[0-9]+\.[0-9]*([eE][+-]?[0-9]+)?", |lex| lex.slice().parse())]
+    #[regex(r"-?\.[0-9]+([eE][+-]?[0-9]+)?", |lex| lex.slice().parse())]
+    #[regex(r"-?[0-9]+[eE][+-]?[0-9]+", |lex| lex.slice().parse())]
     Float(f64),
 
+    #[regex(r"[A-Za-z_][A-Za-z0-9_]*", |lex| Cow::Borrowed(lex.slice()))]
+    Name(Cow<'s, str>),
+
+    #[token("\"", quoted_string)]
+    #[token("'", quoted_string)]
+    #[regex(r"\[=*\[", long_string)]
+    Str(Cow<'s, str>),
+
+    #[token("{")]
+    LBrace,
+
+    #[token("}")]
+    RBrace,
+
+    #[token("[")]
+    LBracket,
+
+    #[token("]")]
+    RBracket,
+
+    #[token("(")]
+    LParen,
+
+    #[token(")")]
+    RParen,
+
+    #[token("=")]
+    Assign,
+
+    #[token(",")]
+    Comma,
+
+    #[token(";")]
+    Semicolon,
+
+    #[token(".")]
+    Dot,
+
+    #[token(":")]
+    Colon,
+
+    #[token("<")]
+    Less,
+
+    #[token(">")]
+    Greater,
+
+    #[token("-")]
+    Minus,
+
+    // Only for the `list[#list+1] =` of appended records.
+    #[token("#")]
+    Length,
+
+    #[token("+")]
+    Plus,
+
+    // Only for `0/0`, `1/0` and `-1/0`; see `ser::format_float`.
+    #[token("/")]
+    Slash,
+
     #[error]
     Error
 }
 
-fn pos_decimal(lex: &mut Lexer<Token>) -> Option<Int> {
+impl<'s> Display for Token<'s> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Bool(b) => write!(formatter, "'{}'", b),
+            Token::Integer(_) | Token::Float(_) => formatter.write_str("number"),
+            Token::Name(name) => write!(formatter, "name '{}'", name),
+            Token::Str(_) => formatter.write_str("string"),
+            Token::Error => formatter.write_str("invalid token"),
+            Token::Whitespace => formatter.write_str("whitespace"),
+            Token::Comment => formatter.write_str("comment"),
+            other => write!(formatter, "'{}'", other.text())
+        }
+    }
+}
+
+impl<'s> Token<'s> {
+    // The source text of keyword and punctuation tokens.
+    fn text(&self) -> &'static str {
+        match self {
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::Assign => "=",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Dot => ".",
+            Token::Colon => ":",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::Minus => "-",
+            Token::Length => "#",
+            Token::Plus => "+",
+            Token::Slash => "/",
+            _ => ""
+        }
+    }
+
+    // Detaches the token from the text it was lexed from.
+    fn into_owned(self) -> Token<'static> {
+        match self {
+            Token::Whitespace => Token::Whitespace,
+            Token::Comment => Token::Comment,
+            Token::And => Token::And,
+            Token::Break => Token::Break,
+            Token::Do => Token::Do,
+            Token::Else => Token::Else,
+            Token::Elseif => Token::Elseif,
+            Token::End => Token::End,
+            Token::For => Token::For,
+            Token::Function => Token::Function,
+            Token::Goto => Token::Goto,
+            Token::If => Token::If,
+            Token::In => Token::In,
+            Token::Local => Token::Local,
+            Token::Nil => Token::Nil,
+            Token::Not => Token::Not,
+            Token::Or => Token::Or,
+            Token::Repeat => Token::Repeat,
+            Token::Return => Token::Return,
+            Token::Then => Token::Then,
+            Token::Until => Token::Until,
+            Token::While => Token::While,
+            Token::Bool(b) => Token::Bool(b),
+            Token::Integer(i) => Token::Integer(i),
+            Token::Float(f) => Token::Float(f),
+            Token::Name(name) => Token::Name(Cow::Owned(name.into_owned())),
+            Token::Str(s) => Token::Str(Cow::Owned(s.into_owned())),
+            Token::LBrace => Token::LBrace,
+            Token::RBrace => Token::RBrace,
+            Token::LBracket => Token::LBracket,
+            Token::RBracket => Token::RBracket,
+            Token::LParen => Token::LParen,
+            Token::RParen => Token::RParen,
+            Token::Assign => Token::Assign,
+            Token::Comma => Token::Comma,
+            Token::Semicolon => Token::Semicolon,
+            Token::Dot => Token::Dot,
+            Token::Colon => Token::Colon,
+            Token::Less => Token::Less,
+            Token::Greater => Token::Greater,
+            Token::Minus => Token::Minus,
+            Token::Length => Token::Length,
+            Token::Plus => Token::Plus,
+            Token::Slash => Token::Slash,
+            Token::Error => Token::Error
+        }
+    }
+}
+
+fn pos_decimal<'s>(lex: &mut Lexer<'s, Token<'s>>) -> Option<Int> {
     let slice = lex.slice();
     let n: u128 = slice.parse().ok()?;
     Some(Int { negative: false, value: n })
 }
 
-fn neg_decimal(lex: &mut Lexer<Token>) -> Option<Int> {
+fn neg_decimal<'s>(lex: &mut Lexer<'s, Token<'s>>) -> Option<Int> {
     let slice = lex.slice();
     let n: u128 = slice[1..slice.len()].parse().ok()?;
     Some(Int { negative: true, value: n })
 }
 
+fn hexadecimal<'s>(lex: &mut Lexer<'s, Token<'s>>) -> Option<Int> {
+    let slice = lex.slice();
+    let n = u128::from_str_radix(&slice[2..], 16).ok()?;
+    Some(Int { negative: false, value: n })
+}
+
+fn neg_hexadecimal<'s>(lex: &mut Lexer<'s, Token<'s>>) -> Option<Int> {
+    let slice = lex.slice();
+    let n = u128::from_str_radix(&slice[3..], 16).ok()?;
+    Some(Int { negative: true, value: n })
+}
+
+// Skips the body of a comment. Long comments (`--[==[ ... ]==]`) may span
+// lines; anything else runs to the end of the line.
+fn comment<'s>(lex: &mut Lexer<'s, Token<'s>>) -> bool {
+    let rest = lex.remainder();
+    match long_bracket_level(rest) {
+        Some(level) => match find_long_bracket_close(&rest[level + 2..], level) {
+            Some(end) => {
+                lex.bump(level + 2 + end + level + 2);
+                true
+            },
+            None => false
+        },
+        None => {
+            let end = rest.find(['\n', '\r']).unwrap_or(rest.len());
+            lex.bump(end);
+            true
+        }
+    }
+}
+
+fn long_string<'s>(lex: &mut Lexer<'s, Token<'s>>) -> Option<Cow<'s, str>> {
+    let level = lex.slice().len() - 2;
+    let rest = lex.remainder();
+    let end = find_long_bracket_close(rest, level)?;
+    lex.bump(end + level + 2);
+
+    // A newline immediately following the opening bracket is not part of
+    // the string.
+    let body = &rest[..end];
+    let body = ["\r\n", "\n\r", "\n", "\r"].iter()
+        .find(|newline| body.starts_with(*newline))
+        .map(|newline| &body[newline.len()..])
+        .unwrap_or(body);
+    Some(Cow::Borrowed(body))
+}
+
+// Returns the level (number of `=`) of a long bracket opening at the start
+// of `text`.
+fn long_bracket_level(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.first() != Some(&b'[') {
+        return None;
+    }
+    let level = bytes[1..].iter().take_while(|b| **b == b'=').count();
+    if bytes.get(level + 1) == Some(&b'[') {
+        Some(level)
+    } else {
+        None
+    }
+}
+
+// Finds the offset of the closing long bracket of the given level.
+fn find_long_bracket_close(text: &str, level: usize) -> Option<usize> {
+    let mut close = String::with_capacity(level + 2);
+    close.push(']');
+    for _ in 0..level {
+        close.push('=');
+    }
+    close.push(']');
+    text.find(&close)
+}
+
+fn quoted_string<'s>(lex: &mut Lexer<'s, Token<'s>>) -> Option<Cow<'s, str>> {
+    let quote = lex.slice().as_bytes()[0];
+    let rest = lex.remainder();
+    let (value, len) = unescape(rest, quote)?;
+    lex.bump(len);
+    Some(value)
+}
+
+// Decodes the body of a quoted string up to and including the closing
+// quote, returning the decoded string and the number of bytes consumed.
+// Strings without escapes are borrowed from `text`.
+fn unescape(text: &str, quote: u8) -> Option<(Cow<'_, str>, usize)> {
+    let bytes = text.as_bytes();
+    let plain = bytes.iter().position(|b| *b == quote || *b == b'\\' || *b == b'\n' || *b == b'\r')?;
+    if bytes[plain] == quote {
+        return Some((Cow::Borrowed(&text[..plain]), plain + 1));
+    }
+    let mut output = bytes[..plain].to_vec();
+    let mut idx = plain;
+    loop {
+        let byte = *bytes.get(idx)?;
+        idx += 1;
+        match byte {
+            b if b == quote => break,
+            b'\n' | b'\r' => return None,
+            b'\\' => {
+                let escape = *bytes.get(idx)?;
+                idx += 1;
+                match escape {
+                    b'a' => output.push(b'\x07'),
+                    b'b' => output.push(b'\x08'),
+                    b'f' => output.push(b'\x0C'),
+                    b'n' => output.push(b'\n'),
+                    b'r' => output.push(b'\r'),
+                    b't' => output.push(b'\t'),
+                    b'v' => output.push(b'\x0B'),
+                    // `ser` escapes brackets, which Lua 5.1 reads as the
+                    // bracket itself.
+                    b'\\' | b'"' | b'\'' | b'[' | b']' => output.push(escape),
+                    b'\n' | b'\r' => {
+                        output.push(b'\n');
+                        let next = bytes.get(idx).copied();
+                        if (next == Some(b'\n') || next == Some(b'\r')) && next != Some(escape) {
+                            idx += 1;
+                        }
+                    },
+                    b'z' => {
+                        while bytes.get(idx).map(|b| b.is_ascii_whitespace() || *b == b'\x0B').unwrap_or(false) {
+                            idx += 1;
+                        }
+                    },
+                    b'x' => {
+                        let digits = text.get(idx..idx + 2)?;
+                        output.push(u8::from_str_radix(digits, 16).ok()?);
+                        idx += 2;
+                    },
+                    b'u' => {
+                        if bytes.get(idx) != Some(&b'{') {
+                            return None;
+                        }
+                        let len = bytes[idx + 1..].iter().position(|b| *b == b'}')?;
+                        let code = u32::from_str_radix(&text[idx + 1..idx + 1 + len], 16).ok()?;
+                        let chr = std::char::from_u32(code)?;
+                        let mut buf = [0; 4];
+                        output.extend_from_slice(chr.encode_utf8(&mut buf).as_bytes());
+                        idx += len + 2;
+                    },
+                    b'0'..=b'9' => {
+                        let len = bytes[idx - 1..].iter()
+                            .take(3)
+                            .take_while(|b| b.is_ascii_digit())
+                            .count();
+                        let code: u16 = text[idx - 1..idx - 1 + len].parse().ok()?;
+                        output.push(u8::try_from(code).ok()?);
+                        idx += len - 1;
+                    },
+                    _ => return None
+                }
+            },
+            b => output.push(b)
+        }
+    }
+    String::from_utf8(output).ok().map(|value| (Cow::Owned(value), idx))
+}
+
+// Where a token starts and ends in the input.
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: usize
+}
 
+// How many bytes a reader is asked for at a time.
+const READ_SIZE: usize = 64 * 1024;
+
+// How much text must follow a token for it to be known to be complete.
+pub const LOOKAHEAD: usize = 16;
+
+enum Input<'s> {
+    // `base` is the offset of the start of the text in the input.
+    Str { lexer: Lexer<'s, Token<'s>>, base: usize },
+    Reader(ReaderInput<'s>)
+}
+
+// Lexes from a reader through a buffer that holds the text from the oldest
+// token still needed up to the end of the most recent read.
+struct ReaderInput<'s> {
+    reader: Box<dyn Read + 's>,
+    buffer: String,
+    // The start of a UTF-8 sequence split across reads, followed by the
+    // bytes of the last read until they are decoded.
+    pending: Vec<u8>,
+    // Where reads go, so that short reads don't each clear a whole buffer.
+    chunk: Vec<u8>,
+    // Offset in the input of the start of `buffer`.
+    base: usize,
+    // Offset in `buffer` at which lexing resumes.
+    next: usize,
+    eof: bool,
+    error: Option<Error>,
+    // Reading stops once more than this many bytes have been read.
+    max_size: usize
+}
+
+impl<'s> ReaderInput<'s> {
+    // Lexes the next token. A token close to the end of the buffer may
+    // continue past it (`1.5` could be the start of `1.5e3`), so it is lexed
+    // again after more input is read. Enough is read to at least double the
+    // text left to lex first, so that a long string or comment is lexed a
+    // few times rather than once per read. Text before `keep` may be dropped
+    // from the buffer.
+    fn lex(&mut self, keep: usize) -> Option<(Token<'static>, Range<usize>)> {
+        loop {
+            let rest = &self.buffer[self.next..];
+            let mut lexer = Token::lexer(rest);
+            let token = lexer.next();
+            let span = lexer.span();
+            let complete = self.eof || match &token {
+                None => false,
+                Some(Token::Error) => !may_continue(&rest[span.start..]),
+                // `[` followed by `=`s may be the start of a long string.
+                Some(token) => rest.len() - span.end >= LOOKAHEAD
+                    && !(*token == Token::LBracket && rest[span.end..].bytes().all(|b| b == b'='))
+            };
+            if complete {
+                let start = self.base + self.next;
+                self.next += span.end;
+                return match token {
+                    Some(token) => Some((token.into_owned(), start + span.start..start + span.end)),
+                    None if self.error.is_some() => Some((Token::Error, start + span.end..start + span.end)),
+                    None => None
+                };
+            }
+            let target = (self.buffer.len() - self.next) * 2;
+            self.refill(keep);
+            while !self.eof && self.buffer.len() - self.next < target {
+                self.refill(keep);
+            }
+        }
+    }
+
+    fn refill(&mut self, keep: usize) {
+        let unused = keep - self.base;
+        if unused > 0 && unused * 2 >= self.buffer.len() {
+            self.buffer.drain(..unused);
+            self.base = keep;
+            self.next -= unused;
+        }
+
+        let room = self.max_size.saturating_add(1).saturating_sub(self.read());
+        if room == 0 {
+            self.eof = true;
+            return;
+        }
+        let size = READ_SIZE.min(room);
+        let count = match self.reader.read(&mut self.chunk[..size]) {
+            Ok(count) => count,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => return,
+            Err(error) => {
+                self.fail(error.into());
+                return;
+            }
+        };
+        let start = self.pending.len();
+        self.pending.extend_from_slice(&self.chunk[..count]);
+        if count == 0 && start == 0 {
+            self.eof = true;
+            return;
+        }
+        let offset = self.base + self.buffer.len();
+        match str::from_utf8(&self.pending) {
+            Ok(text) => {
+                self.buffer.push_str(text);
+                self.pending.clear();
+            },
+            Err(error) => {
+                let valid = error.valid_up_to();
+                self.buffer.push_str(str::from_utf8(&self.pending[..valid]).unwrap());
+                self.pending.drain(..valid);
+                if error.error_len().is_some() || count == 0 {
+                    self.fail(Error::InvalidUtf8 { valid_up_to: offset + valid, error_len: error.error_len() });
+                }
+            }
+        }
+    }
+
+    // How many bytes have been read so far.
+    fn read(&self) -> usize {
+        self.base + self.buffer.len() + self.pending.len()
+    }
+
+    fn fail(&mut self, error: Error) {
+        self.eof = true;
+        self.error = Some(error);
+    }
+}
+
+// Whether text that failed to lex could lex once more input is available:
+// an unfinished string, long string, long comment or number.
+fn may_continue(text: &str) -> bool {
+    text.starts_with(|c: char| c == '"' || c == '\'' || c == '[' || c == '-' || c == '.' || c.is_ascii_digit())
+}
+
+// The position at the end of `text`, which starts at `start`.
+fn position_after(start: Position, text: &str) -> Position {
+    let lines = text.bytes().filter(|b| *b == b'\n').count();
+    let column = match text.rfind('\n') {
+        Some(idx) => text[idx + 1..].chars().count() + 1,
+        None => start.column + text.chars().count()
+    };
+    Position { offset: start.offset + text.len(), line: start.line + lines, column }
+}
+
+// A lookahead buffer over the lexer that drops comments and keeps track of
+// token positions.
+pub struct Tokens<'s> {
+    input: Input<'s>,
+    peeked: VecDeque<(Token<'s>, Span)>,
+    // The position of the most recently lexed token.
+    cursor: Position,
+    // The start of the last token returned by `next`.
+    last: usize,
+    last_span: Span,
+    // How many brackets the tokens returned by `next` leave open.
+    depth: usize,
+    // Whether the last token returned by `next` was a name.
+    after_name: bool,
+    max_size: usize,
+    max_string: usize,
+    // The limit the input went past, which ends it with an error token.
+    limit_error: Option<Error>
+}
+
+impl<'s> Tokens<'s> {
+    pub fn new(source: &'s str) -> Tokens<'s> {
+        Tokens::at(source, Position { offset: 0, line: 1, column: 1 })
+    }
+
+    // Lexes `source` as the part of a larger input starting at `start`.
+    pub fn at(source: &'s str, start: Position) -> Tokens<'s> {
+        let mut tokens = Tokens::with_input(Input::Str { lexer: Token::lexer(source), base: start.offset });
+        tokens.cursor = start;
+        tokens.last = start.offset;
+        tokens.last_span = Span { start, end: start.offset };
+        tokens
+    }
+
+    // Lexes the text of `reader`, holding only as much of it in memory as
+    // the tokens being looked at need.
+    pub fn from_reader<R: Read + 's>(reader: R) -> Tokens<'s> {
+        Tokens::with_input(Input::Reader(ReaderInput {
+            reader: Box::new(reader),
+            buffer: String::new(),
+            pending: Vec::new(),
+            chunk: vec![0; READ_SIZE],
+            base: 0,
+            next: 0,
+            eof: false,
+            error: None,
+            max_size: usize::MAX
+        }))
+    }
+
+    fn with_input(input: Input<'s>) -> Tokens<'s> {
+        Tokens {
+            input,
+            peeked: VecDeque::new(),
+            cursor: Position { offset: 0, line: 1, column: 1 },
+            last: 0,
+            last_span: Span { start: Position { offset: 0, line: 1, column: 1 }, end: 0 },
+            depth: 0,
+            after_name: false,
+            max_size: usize::MAX,
+            max_string: usize::MAX,
+            limit_error: None
+        }
+    }
+
+    // Ends the input with an error once it is longer than `max_size` bytes,
+    // or at a string longer than `max_string` bytes.
+    pub fn set_limits(&mut self, max_size: usize, max_string: usize) {
+        self.max_size = max_size;
+        self.max_string = max_string;
+        if let Input::Reader(reader) = &mut self.input {
+            reader.max_size = max_size;
+        }
+    }
+
+    fn fill(&mut self, count: usize) {
+        while self.peeked.len() < count && self.limit_error.is_none() {
+            let next = match &mut self.input {
+                Input::Str { lexer, base } => lexer.next().map(|token| {
+                    let span = lexer.span();
+                    (token, *base + span.start..*base + span.end)
+                }),
+                Input::Reader(reader) => reader.lex(self.last)
+            };
+            match next {
+                Some((Token::Comment, _)) => continue,
+                Some((token, range)) => {
+                    let start = self.advance(range.start);
+                    let span = Span { start, end: range.end };
+                    match self.exceeded(&token, range.end) {
+                        Some((limit, max)) => {
+                            self.limit_error = Some(Error::LimitExceeded { limit, max, position: start });
+                            self.peeked.push_back((Token::Error, span));
+                        },
+                        None => self.peeked.push_back((token, span))
+                    }
+                },
+                None => {
+                    if self.size() > self.max_size {
+                        let end = self.end();
+                        let start = self.advance(end);
+                        let max = self.max_size;
+                        self.limit_error = Some(Error::LimitExceeded { limit: Limit::InputSize, max, position: start });
+                        self.peeked.push_back((Token::Error, Span { start, end }));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    // The limit, if any, that `token`, ending at `end`, is past.
+    fn exceeded(&self, token: &Token<'s>, end: usize) -> Option<(Limit, usize)> {
+        match token {
+            _ if end > self.max_size => Some((Limit::InputSize, self.max_size)),
+            Token::Str(s) if s.len() > self.max_string => Some((Limit::StringLength, self.max_string)),
+            _ => None
+        }
+    }
+
+    // The size of the input, or for a reader of as much as has been read.
+    fn size(&self) -> usize {
+        match &self.input {
+            Input::Str { .. } => self.end(),
+            Input::Reader(reader) => reader.read()
+        }
+    }
+
+    // Moves the cursor forward to `offset` and returns its position.
+    fn advance(&mut self, offset: usize) -> Position {
+        self.cursor = position_after(self.cursor, self.text(self.cursor.offset..offset));
+        self.cursor
+    }
+
+    // The text at `range`, which must not have been dropped yet.
+    fn text(&self, range: Range<usize>) -> &str {
+        match &self.input {
+            Input::Str { lexer, base } => &lexer.source()[range.start - base..range.end - base],
+            Input::Reader(reader) => &reader.buffer[range.start - reader.base..range.end - reader.base]
+        }
+    }
+
+    // The offset just past the text available so far.
+    fn end(&self) -> usize {
+        match &self.input {
+            Input::Str { lexer, base } => base + lexer.source().len(),
+            Input::Reader(reader) => reader.base + reader.buffer.len()
+        }
+    }
+
+    pub fn peek(&mut self) -> Option<&Token<'s>> {
+        self.peek_nth(0)
+    }
+
+    pub fn peek_nth(&mut self, idx: usize) -> Option<&Token<'s>> {
+        self.fill(idx + 1);
+        self.peeked.get(idx).map(|(token, _)| token)
+    }
+
+    // The name of a `name = value` table entry starting at the next token.
+    pub fn peek_field_name(&mut self) -> Option<Cow<'s, str>> {
+        match (self.peek_nth(0).cloned(), self.peek_nth(1)) {
+            (Some(Token::Name(name)), Some(Token::Assign)) => Some(name),
+            _ => None
+        }
+    }
+
+    // The span of the next token, or an empty span at the end of the input.
+    pub fn peek_span(&mut self) -> Span {
+        self.fill(1);
+        match self.peeked.front() {
+            Some((_, span)) => *span,
+            None => {
+                let end = self.end();
+                Span { start: self.advance(end), end }
+            }
+        }
+    }
+
+    pub fn next(&mut self) -> Option<(Token<'s>, Span)> {
+        self.fill(1);
+        let next = self.peeked.pop_front();
+        if let Some((token, span)) = &next {
+            self.last = span.start.offset;
+            self.last_span = *span;
+            match token {
+                Token::LBrace | Token::LParen | Token::LBracket => self.depth += 1,
+                Token::RBrace | Token::RParen | Token::RBracket => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+            self.after_name = matches!(token, Token::Name(_));
+        }
+        next
+    }
+
+    // The position just past the last token returned by `next`.
+    pub fn last_end(&self) -> Position {
+        let span = self.last_span;
+        position_after(span.start, self.text(span.start.offset..span.end))
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn after_name(&self) -> bool {
+        self.after_name
+    }
+
+    // The error that stopped reading the input, if any. Reading stops with
+    // an error token.
+    pub fn read_error(&self) -> Option<&Error> {
+        if self.limit_error.is_some() {
+            return self.limit_error.as_ref();
+        }
+        match &self.input {
+            Input::Reader(reader) => reader.error.as_ref(),
+            Input::Str { .. } => None
+        }
+    }
+
+    // Explains why the text at `span`, the last token returned by `next` or
+    // the next token, failed to lex.
+    pub fn describe_error(&self, span: &Span) -> String {
+        let text = self.text(span.start.offset..self.end());
+        if text.starts_with("--") {
+            "unfinished long comment".to_string()
+        } else if text.starts_with('"') || text.starts_with('\'') {
+            "unfinished string or invalid escape sequence".to_string()
+        } else if long_bracket_level(text).is_some() {
+            "unfinished long string".to_string()
+        } else if text.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+            format!("malformed number near '{}'", &text[..span.end - span.start.offset])
+        } else {
+            match text.chars().next() {
+                Some(chr) => format!("unexpected character '{}'", chr.escape_default()),
+                None => "unexpected end of input".to_string()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(text: &str) -> Vec<Token<'_>> {
+        Token::lexer(text).collect()
+    }
+
+    fn int(negative: bool, value: u128) -> Token<'static> {
+        Token::Integer(Int { negative, value })
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(lex("12 -7 0x1F 0XfF"), vec![int(false, 12), int(true, 7), int(false, 31), int(false, 255)]);
+    }
+
+    #[test]
+    fn negative_hexadecimal() {
+        assert_eq!(lex("-0x10"), vec![int(true, 16)]);
+        assert_eq!(lex("-0x8000000000000000"), vec![int(true, 1 << 63)]);
+        match &lex("-0x8000000000000000")[0] {
+            Token::Integer(i) => assert_eq!(i.to_i128(), Some(i128::from(i64::MIN))),
+            token => panic!("unexpected {:?}", token)
+        }
+    }
+
+    #[test]
+    fn keywords_and_names() {
+        assert_eq!(lex("local x <const> = nil"), vec![
+            Token::Local,
+            Token::Name(Cow::Borrowed("x")),
+            Token::Less,
+            Token::Name(Cow::Borrowed("const")),
+            Token::Greater,
+            Token::Assign,
+            Token::Nil
+        ]);
+    }
+
+    #[test]
+    fn strings() {
+        match &lex("'plain' [==[long]]string]==]")[..] {
+            [Token::Str(Cow::Borrowed("plain")), Token::Str(Cow::Borrowed("long]]string"))] => {},
+            tokens => panic!("unexpected {:?}", tokens)
+        }
+        match &lex(r#""a\tb\u{2603}\65\x41""#)[..] {
+            [Token::Str(Cow::Owned(s))] => assert_eq!(s, "a\tb\u{2603}AA"),
+            tokens => panic!("unexpected {:?}", tokens)
+        }
+        assert_eq!(lex("'unfinished")[0], Token::Error);
+    }
+
+    #[test]
+    fn token_positions() {
+        let mut tokens = Tokens::new("do\n  x -- comment\nend");
+        assert_eq!(tokens.next().unwrap().1.start, Position { offset: 0, line: 1, column: 1 });
+        assert_eq!(tokens.next().unwrap().1.start, Position { offset: 5, line: 2, column: 3 });
+        assert_eq!(tokens.next().unwrap().1.start, Position { offset: 18, line: 3, column: 1 });
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn large_integers() {
+        let ints: Vec<_> = lex("170141183460469231731687303715884105728 -170141183460469231731687303715884105729")
+            .into_iter()
+            .map(|token| match token {
+                Token::Integer(i) => (i.to_i128(), i.to_f64()),
+                token => panic!("unexpected {:?}", token)
+            })
+            .collect();
+        assert_eq!(ints, vec![(None, 1.7014118346046923e38), (None, -1.7014118346046923e38)]);
+    }
+}
@@ -1,29 +1,31 @@
 
 use serde::{ser, Serialize};
 use crate::error::{Error, Result};
+use crate::symbols::Symbols;
+use crate::value::{Key, Value};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::io::{Write};
 use std::str;
 
 pub fn to_writer<T: Serialize, W: Write>(value: &T, writer: &mut W) -> Result<()> {
-    let mut serializer = Serializer {
-        indent_str: "  ".to_string(),
-        indent: vec![],
-        enable_indent: false,
-        serializing_key: false,
-        output: writer
-    };
+    let mut serializer = Serializer::new(writer);
     value.serialize(&mut serializer)?;
     Ok(())
 }
 
 pub fn to_writer_pretty<T: Serialize, W: Write>(value: &T, writer: &mut W) -> Result<()> {
-    let mut serializer = Serializer {
-        indent_str: "  ".to_string(),
-        indent: vec![],
-        enable_indent: true,
-        serializing_key: false,
-        output: writer
-    };
+    let mut serializer = Serializer::pretty(writer);
+    value.serialize(&mut serializer)?;
+    Ok(())
+}
+
+// Writes `value` compactly in canonical form; see
+// `Serializer::set_canonical`.
+pub fn to_writer_canonical<T: Serialize, W: Write>(value: &T, writer: &mut W) -> Result<()> {
+    let mut serializer = Serializer::new(writer);
+    serializer.set_canonical(true);
     value.serialize(&mut serializer)?;
     Ok(())
 }
@@ -31,24 +33,420 @@ pub fn to_writer_pretty<T: Serialize, W: Write>(value: &T, writer: &mut W) -> Re
 pub fn to_string<T: Serialize>(value: &T) -> Result<String> {
     let mut output: Vec<u8> = Vec::new();
     to_writer(value, &mut output)?;
-    Ok(str::from_utf8(&output)?.to_string())
+    Ok(String::from_utf8(output).map_err(|error| error.utf8_error())?)
 }
 
 pub fn to_string_pretty<T: Serialize>(value: &T) -> Result<String> {
     let mut output: Vec<u8> = Vec::new();
     to_writer_pretty(value, &mut output)?;
-    Ok(str::from_utf8(&output)?.to_string())
+    Ok(String::from_utf8(output).map_err(|error| error.utf8_error())?)
+}
+
+pub fn to_string_canonical<T: Serialize>(value: &T) -> Result<String> {
+    let mut output: Vec<u8> = Vec::new();
+    to_writer_canonical(value, &mut output)?;
+    Ok(String::from_utf8(output).map_err(|error| error.utf8_error())?)
+}
+
+// How `RecordWriter` writes each record.
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum RecordStyle {
+    // `{ ... }`
+    Table,
+    // `Name { ... }`, named after the record's type.
+    Constructor,
+    // `list[#list+1] = { ... }`, appending to a list that the file, or
+    // whatever loads it, has already declared. `StreamDeserializer` reads
+    // these as plain records.
+    Append(String)
+}
+
+// Appends records to a log, one per line. Each record is written with a
+// single write followed by a flush, so the output only ever ends between
+// records.
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    style: RecordStyle,
+    buffer: Vec<u8>
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W, style: RecordStyle) -> RecordWriter<W> {
+        RecordWriter { writer, style, buffer: Vec::new() }
+    }
+
+    pub fn write<T: Serialize + ?Sized>(&mut self, record: &T) -> Result<()> {
+        self.buffer.clear();
+        if let RecordStyle::Append(list) = &self.style {
+            if !is_identifier(list) {
+                return Err(Error::Message(format!("cannot append to '{}': not a name", list)));
+            }
+            write!(self.buffer, "{}[#{}+1] = ", list, list)?;
+        }
+        let mut serializer = Serializer::new(&mut self.buffer);
+        serializer.set_constructor_calls(self.style == RecordStyle::Constructor);
+        record.serialize(&mut serializer)?;
+        self.buffer.push(b'\n');
+        self.writer.write_all(&self.buffer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Number {
+    Integer(i128),
+    Float(f64)
+}
+
+macro_rules! number_from {
+    ($variant:ident, $cast:ty, $($from:ty)*) => {
+        $(impl From<$from> for Number {
+            fn from(n: $from) -> Number {
+                Number::$variant(<$cast>::from(n))
+            }
+        })*
+    }
+}
+
+number_from!(Integer, i128, i8 i16 i32 i64 i128 u8 u16 u32 u64);
+number_from!(Float, f64, f32 f64);
+
+#[derive(Clone,Copy,PartialEq)]
+enum Expect {
+    Value,
+    // A key, a positional item, the end of the table or a comment.
+    Entry,
+    // The value of an entry whose key has been written.
+    EntryValue,
+    Done
+}
+
+// Writes LSON a piece at a time, for data without a `Serialize` type. Keys
+// and values are written in order, with `begin_table` and `end_table`
+// around the entries of a table; each entry starts with `key_str`,
+// `key_int` or `positional`. Calls out of order are errors.
+pub struct EventWriter<'a, W: Write> {
+    serializer: Serializer<'a, W>,
+    depth: usize,
+    expect: Expect
+}
+
+impl<'a, W: Write> EventWriter<'a, W> {
+    pub fn new(output: &'a mut W) -> EventWriter<'a, W> {
+        EventWriter { serializer: Serializer::new(output), depth: 0, expect: Expect::Value }
+    }
+
+    pub fn pretty(output: &'a mut W) -> EventWriter<'a, W> {
+        EventWriter { serializer: Serializer::pretty(output), depth: 0, expect: Expect::Value }
+    }
+
+    pub fn begin_table(&mut self) -> Result<()> {
+        self.check_value("a table")?;
+        self.serializer.start_table()?;
+        self.depth += 1;
+        self.expect = Expect::Entry;
+        Ok(())
+    }
+
+    pub fn end_table(&mut self) -> Result<()> {
+        self.check(Expect::Entry, "the end of a table")?;
+        self.serializer.end_table()?;
+        self.depth -= 1;
+        self.end_value();
+        Ok(())
+    }
+
+    // Starts a `name = ` or `["key"] = ` entry.
+    pub fn key_str(&mut self, key: &str) -> Result<()> {
+        self.check(Expect::Entry, "a key")?;
+        self.serializer.start_entry()?;
+        self.serializer.write_str_key(key)?;
+        self.serializer.write(" = ")?;
+        self.expect = Expect::EntryValue;
+        Ok(())
+    }
+
+    // Starts a `[key] = ` entry.
+    pub fn key_int(&mut self, key: i128) -> Result<()> {
+        self.check(Expect::Entry, "a key")?;
+        self.serializer.start_entry()?;
+        self.serializer.write("[")?;
+        ser::Serializer::serialize_i128(&mut self.serializer, key)?;
+        self.serializer.write("] = ")?;
+        self.expect = Expect::EntryValue;
+        Ok(())
+    }
+
+    // Starts a positional item.
+    pub fn positional(&mut self) -> Result<()> {
+        self.check(Expect::Entry, "a positional item")?;
+        self.serializer.start_entry()?;
+        self.expect = Expect::EntryValue;
+        Ok(())
+    }
+
+    pub fn string(&mut self, value: &str) -> Result<()> {
+        self.check_value("a string")?;
+        ser::Serializer::serialize_str(&mut self.serializer, value)?;
+        self.end_value();
+        Ok(())
+    }
+
+    // Floats are written the way `Serializer` writes them.
+    pub fn number<N: Into<Number>>(&mut self, value: N) -> Result<()> {
+        self.check_value("a number")?;
+        match value.into() {
+            Number::Integer(n) => ser::Serializer::serialize_i128(&mut self.serializer, n)?,
+            Number::Float(n) => ser::Serializer::serialize_f64(&mut self.serializer, n)?
+        }
+        self.end_value();
+        Ok(())
+    }
+
+    pub fn boolean(&mut self, value: bool) -> Result<()> {
+        self.check_value("a boolean")?;
+        ser::Serializer::serialize_bool(&mut self.serializer, value)?;
+        self.end_value();
+        Ok(())
+    }
+
+    pub fn nil(&mut self) -> Result<()> {
+        self.check_value("nil")?;
+        ser::Serializer::serialize_unit(&mut self.serializer)?;
+        self.end_value();
+        Ok(())
+    }
+
+    // Writes a comment between table entries, or before or after the value
+    // at the top level. Pretty output uses `--` line comments; compact
+    // output stays on one line with long comments.
+    pub fn comment(&mut self, text: &str) -> Result<()> {
+        if self.expect == Expect::EntryValue {
+            return Err(Error::Message("expected a value, found a comment".to_string()));
+        }
+        if self.depth > 0 {
+            if !self.serializer.is_table_start() {
+                self.serializer.write(",")?;
+            }
+            self.serializer.write(if self.serializer.enable_indent() { "\n" } else { " " })?;
+            self.serializer.write_indent()?;
+        } else if self.expect == Expect::Done {
+            self.serializer.write(if self.serializer.enable_indent() { "\n" } else { " " })?;
+        }
+
+        if self.serializer.enable_indent() {
+            for (idx, line) in text.split('\n').enumerate() {
+                if idx > 0 {
+                    self.serializer.write("\n")?;
+                    self.serializer.write_indent()?;
+                }
+                self.serializer.write("-- ")?;
+                self.serializer.write(line)?;
+            }
+        } else {
+            let mut level = 0;
+            while text.contains(&format!("]{}]", "=".repeat(level))) {
+                level += 1;
+            }
+            let equals = "=".repeat(level);
+            self.serializer.write(&format!("--[{}[ {} ]{}]", equals, text, equals))?;
+        }
+
+        if self.depth > 0 {
+            // The separator has been written, so the next entry starts like
+            // the first one.
+            self.serializer.set_table_start();
+        } else if self.expect == Expect::Value {
+            self.serializer.write(if self.serializer.enable_indent() { "\n" } else { " " })?;
+        }
+        Ok(())
+    }
+
+    // Checks that the top-level value and every table have been completed.
+    pub fn end(self) -> Result<()> {
+        match self.expect {
+            Expect::Done => Ok(()),
+            Expect::Value => Err(Error::Message("nothing has been written".to_string())),
+            _ => Err(Error::Message(format!("{} table(s) left open", self.depth)))
+        }
+    }
+
+    fn check(&self, expected: Expect, found: &str) -> Result<()> {
+        if self.expect == expected {
+            return Ok(());
+        }
+        let expected = match self.expect {
+            Expect::Value | Expect::EntryValue => "a value",
+            Expect::Entry => "a key, a positional item or the end of the table",
+            Expect::Done => "nothing after the top-level value"
+        };
+        Err(Error::Message(format!("expected {}, found {}", expected, found)))
+    }
+
+    fn check_value(&self, found: &str) -> Result<()> {
+        match self.expect {
+            Expect::Value | Expect::EntryValue => Ok(()),
+            _ => self.check(Expect::Value, found)
+        }
+    }
+
+    fn end_value(&mut self) {
+        self.expect = if self.depth == 0 { Expect::Done } else { Expect::Entry };
+    }
 }
 
 pub struct Serializer<'a, Writer: Write> {
-    indent_str: String,
+    indent_str: &'static str,
     indent: Vec<bool>,
     enable_indent: bool,
-    serializing_key: bool,
+    constructor_calls: bool,
+    compact_arrays: bool,
+    zero_based_arrays: bool,
+    symbols: Symbols,
+    // One entry per open constructor call; true until its first argument
+    // has been written.
+    calls: Vec<bool>,
+    canonical: bool,
+    strict: bool,
+    // In strict mode, why the value being written can't be `nil`, if it
+    // can't.
+    nil_context: Option<&'static str>,
+    // One entry per open map in strict mode; the keys written so far.
+    keys: Vec<HashSet<Key>>,
+    // One entry per open map; the index the next positional item would get.
+    items: Vec<i128>,
+    // One entry per open map that holds its entries back until they can be
+    // sorted; see `holds_entries`.
+    entries: Vec<Vec<Entry>>,
+    // Output collected for held map entries, innermost last.
+    captures: Vec<Vec<u8>>,
+    // How the last map key written sorts, if it's needed.
+    sort_key: Option<SortKey>,
+    // Whether the last map key was written as a positional item, with no
+    // `[key] =`.
+    positional_key: bool,
     output: &'a mut Writer
 }
 
 impl<'a, Writer: Write> Serializer<'a, Writer> {
+    pub fn new(output: &'a mut Writer) -> Serializer<'a, Writer> {
+        Serializer {
+            indent_str: "  ",
+            indent: vec![],
+            enable_indent: false,
+            constructor_calls: false,
+            compact_arrays: false,
+            zero_based_arrays: false,
+            symbols: Symbols::new(),
+            calls: vec![],
+            canonical: false,
+            strict: false,
+            nil_context: None,
+            keys: vec![],
+            items: vec![],
+            entries: vec![],
+            captures: vec![],
+            sort_key: None,
+            positional_key: false,
+            output
+        }
+    }
+
+    pub fn pretty(output: &'a mut Writer) -> Serializer<'a, Writer> {
+        let mut serializer = Serializer::new(output);
+        serializer.enable_indent = true;
+        serializer
+    }
+
+    // Writes structs as `Name { ... }` and enum variants as `Variant(...)`
+    // or `Variant { ... }` instead of plain and externally tagged tables.
+    pub fn set_constructor_calls(&mut self, enabled: bool) {
+        self.constructor_calls = enabled;
+    }
+
+    // Writes map entries whose keys continue the sequence 1, 2, 3, ... as
+    // positional items, so that maps with contiguous keys from 1 come out as
+    // plain lists that work with `ipairs` and `#`. Entries are written in
+    // key order, which is what a `Vec` needs to read back a list whose keys
+    // are written out: `{ [2] = "b", [1] = "a" }` is rejected as a `Vec`,
+    // though it reads as a map.
+    pub fn set_compact_arrays(&mut self, enabled: bool) {
+        self.compact_arrays = enabled;
+    }
+
+    // With compact arrays, writes maps whose keys are exactly 0, 1, 2, ...
+    // as plain lists, shifting every key up by one. Other maps keep their
+    // keys as they are. Either way, entries are written sorted by key like
+    // in canonical mode, since the whole map has to be seen first. Read the
+    // output back with `de::Deserializer::set_zero_based_arrays`.
+    pub fn set_zero_based_arrays(&mut self, enabled: bool) {
+        self.zero_based_arrays = enabled;
+    }
+
+    // Writes output that is byte-identical for equal data: map entries are
+    // sorted by key, with numbers in numeric order before strings in
+    // bytewise order and booleans last, integral float keys are written as
+    // integers and `-0.0` as `0.0`. Struct fields keep their declared order,
+    // and so do sequences, which can't be told apart from lists: a `HashSet`
+    // is written in its iteration order unless it goes through `as_set`,
+    // which writes it as a map.
+    pub fn set_canonical(&mut self, enabled: bool) {
+        self.canonical = enabled;
+    }
+
+    // Propagates every error and refuses to write data that Lua would read
+    // back differently: `nil` where Lua drops it (list items, map values,
+    // the value of a variant or of `Some`), keys that are duplicates once
+    // Lua normalizes them, NaN and infinities, and integers that don't fit
+    // in Lua's 64-bit integers.
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    // Writes unit variants of enums registered in `symbols` as dotted names
+    // like `Align.Center`.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn start_call(&mut self, name: &str) -> Result<()> {
+        self.write_constructor(name)?;
+        self.write("(")?;
+        self.calls.push(true);
+        Ok(())
+    }
+
+    pub fn end_call(&mut self) -> Result<()> {
+        self.calls.pop();
+        self.write(")")
+    }
+
+    pub fn write_argument_separator(&mut self) -> Result<()> {
+        if let Some(first) = self.calls.last_mut() {
+            if *first {
+                *first = false;
+                return Ok(());
+            }
+        }
+        self.write(", ")
+    }
+
+    fn write_constructor(&mut self, name: &str) -> Result<()> {
+        if !is_identifier(name) {
+            return Err(Error::Message(format!("cannot write '{}' as a constructor call", name)));
+        }
+        self.write(name)
+    }
+
     #[inline]
     pub fn enable_indent(&self) -> bool {
         self.enable_indent
@@ -60,7 +458,7 @@ impl<'a, Writer: Write> Serializer<'a, Writer> {
         }
 
         for _idx in 0..self.indent.len() {
-            self.output.write_all(self.indent_str.as_bytes())?;
+            self.write(self.indent_str)?;
         }
         Ok(())
     }
@@ -70,27 +468,52 @@ impl<'a, Writer: Write> Serializer<'a, Writer> {
     }
 
     pub fn dedent(&mut self) {
-        if self.indent.len() > 0 {
+        if !self.indent.is_empty() {
             self.indent.pop();
         }
     }
 
     pub fn is_table_start(&self) -> bool {
-        if self.indent.len() == 0 {
+        if self.indent.is_empty() {
             return false;
         }
         self.indent[self.indent.len() - 1]
     }
 
+    pub fn set_table_start(&mut self) {
+        if let Some(start) = self.indent.last_mut() {
+            *start = true;
+        }
+    }
+
     pub fn clear_table_start(&mut self) {
-        if self.indent.len() > 0 {
+        if !self.indent.is_empty() {
             let last = self.indent.len() - 1;
             self.indent[last] = false;
         }
     }
 
+    // Writes the separator and indentation that go before a table entry.
+    pub fn start_entry(&mut self) -> Result<()> {
+        if !self.is_table_start() {
+            if self.enable_indent() {
+                self.write(",\n")?;
+            } else {
+                self.write(", ")?;
+            }
+        } else {
+            if self.enable_indent() {
+                self.write("\n")?;
+            } else {
+                self.write(" ")?;
+            }
+            self.clear_table_start();
+        }
+        self.write_indent()
+    }
+
     pub fn start_table(&mut self) -> Result<()> {
-        self.output.write_all("{".as_bytes())?;
+        self.write("{")?;
         self.indent();
         Ok(())
     }
@@ -108,20 +531,139 @@ impl<'a, Writer: Write> Serializer<'a, Writer> {
     }
 
     pub fn write(&mut self, text: &str) -> Result<()> {
-        self.output.write_all(text.as_bytes())?;
+        self.write_bytes(text.as_bytes())
+    }
+
+    // Writes to the innermost capture, if a canonical map is collecting its
+    // entries, and otherwise to the output.
+    fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        match self.captures.last_mut() {
+            Some(capture) => capture.extend_from_slice(bytes),
+            None => self.output.write_all(bytes)?
+        }
+        Ok(())
+    }
+
+    // Runs `write` with its output collected instead of written.
+    fn capture<F>(&mut self, write: F) -> (Vec<u8>, Result<()>)
+    where
+        F: FnOnce(&mut Serializer<'a, Writer>) -> Result<()>
+    {
+        self.captures.push(Vec::new());
+        let result = write(self);
+        (self.captures.pop().unwrap(), result)
+    }
+
+    // In strict mode, fails if the current map already has `key`.
+    fn check_duplicate(&mut self, key: &SortKey) -> Result<()> {
+        let key = match key {
+            SortKey::Integer(i) => Key::Integer(*i),
+            SortKey::Float(f) => match Value::Float(*f).into_key() {
+                Some(key) => key,
+                None => return Err(key_error("NaN"))
+            },
+            SortKey::String(s) => Key::String(s.clone()),
+            SortKey::Boolean(b) => Key::Boolean(*b)
+        };
+        if let Some(keys) = self.keys.last_mut() {
+            if keys.contains(&key) {
+                let message = format!("the key {} is written twice once Lua normalizes it", key);
+                return Err(Error::Message(message));
+            }
+            keys.insert(key);
+        }
+        Ok(())
+    }
+
+    // Whether maps hold their entries back until the end: to sort them in
+    // canonical mode, and with zero-based compact arrays to see whether all
+    // of the keys count from 0.
+    fn holds_entries(&self) -> bool {
+        self.canonical || (self.compact_arrays && self.zero_based_arrays)
+    }
+
+    // Writes the entries of a map that held them back, sorted by key.
+    fn write_sorted(&mut self, mut entries: Vec<Entry>) -> Result<()> {
+        entries.sort_by(compare_entries);
+        // The index the next positional item would get, if keys are written
+        // as positional items at all.
+        let mut next = match (self.compact_arrays, self.zero_based_arrays) {
+            (true, false) => Some(1),
+            (true, true) if counts_from_zero(&entries) => Some(0),
+            _ => None
+        };
+        for entry in entries {
+            self.start_entry()?;
+            match &mut next {
+                Some(index) if entry.key == SortKey::Integer(*index) => *index += 1,
+                _ => {
+                    self.write_bytes(&entry.text)?;
+                    self.write(" = ")?;
+                }
+            }
+            self.write_bytes(&entry.value)?;
+        }
         Ok(())
     }
+
+    // Writes a string table key, bare if it's an identifier.
+    fn write_str_key(&mut self, key: &str) -> Result<()> {
+        if is_identifier(key) {
+            return self.write(key);
+        }
+        self.write("[\"")?;
+        self.write_escaped(key)?;
+        self.write("\"]")
+    }
+
+    // Writes the contents of a quoted string, copying the runs of characters
+    // that need no escape in one go.
+    fn write_escaped(&mut self, text: &str) -> Result<()> {
+        let mut start = 0;
+        for (idx, byte) in text.bytes().enumerate() {
+            let escape = match byte {
+                b'"' => "\\\"",
+                b'\'' => "\\'",
+                b'\x07' => "\\a",
+                b'\x08' => "\\b",
+                b'\x0C' => "\\f",
+                b'\n' => "\\n",
+                b'\r' => "\\r",
+                b'\t' => "\\t",
+                b'\x0B' => "\\v",
+                b'\\' => "\\\\",
+                b'[' => "\\[",
+                b']' => "\\]",
+                _ => continue
+            };
+            // Escaped characters are ASCII, so `idx` is on a char boundary.
+            self.write(&text[start..idx])?;
+            self.write(escape)?;
+            start = idx + 1;
+        }
+        self.write(&text[start..])
+    }
+}
+
+// Formats a float so that Lua reads it back as the same float: always with
+// a `.` or an exponent, and NaN and the infinities as `0/0`, `1/0` and
+// `-1/0`, since Lua has no literals for them.
+fn format_float(buffer: &mut ryu::Buffer, v: f64) -> &str {
+    if v.is_nan() {
+        "0/0"
+    } else if v.is_infinite() {
+        if v > 0.0 { "1/0" } else { "-1/0" }
+    } else {
+        buffer.format_finite(v)
+    }
 }
 
 fn is_identifier_char(chr: char) -> bool {
-    (chr >= 'a' && chr <= 'z')
-        || (chr >= 'A' && chr <= 'Z')
-        || (chr >= '0' && chr <= '9')
-        || chr == '_'
+    chr.is_ascii_alphanumeric() || chr == '_'
 }
 
 pub fn is_identifier(name: &str) -> bool {
-    if name.len() == 0 {
+    if name.is_empty() {
         return false;
     }
 
@@ -131,18 +673,16 @@ pub fn is_identifier(name: &str) -> bool {
         }
     }
 
-    let first = name.chars().nth(0).unwrap();
-    if first >= '0' && first <= '9' {
-        return false
+    let first = name.chars().next().unwrap();
+    if first.is_ascii_digit() {
+        false
     } else {
-        match name {
+        !matches!(name,
             "and" | "break" | "do" | "else" | "elseif" | "end"
                 | "false" | "for" | "function" | "goto"
                 | "if" | "in" | "local" | "nil" | "not" | "or"
                 | "repeat" | "return" | "then" | "true" | "until"
-                | "while" => false,
-            _ => true
-        }
+                | "while")
     }
 }
 
@@ -159,7 +699,7 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     type SerializeStructVariant = Self;
 
     fn serialize_bool(self, v: bool) -> Result<()> {
-        self.write((if v { "true" } else { "false" }))?;
+        self.write(if v { "true" } else { "false" })?;
         Ok(())
     }
 
@@ -167,94 +707,77 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     // signed integers will be serialized the same and all unsigned integers
     // will be serialized the same.
     fn serialize_i8(self, v: i8) -> Result<()> {
-        self.serialize_i128(i128::from(v))
+        self.serialize_i64(i64::from(v))
     }
 
     fn serialize_i16(self, v: i16) -> Result<()> {
-        self.serialize_i128(i128::from(v))
+        self.serialize_i64(i64::from(v))
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        self.serialize_i128(i128::from(v))
+        self.serialize_i64(i64::from(v))
     }
 
-    // Not particularly efficient but this is example code anyway. A more
-    // performant approach would be to use the `itoa` crate.
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.serialize_i128(i128::from(v))
+        self.write(itoa::Buffer::new().format(v))
     }
 
     fn serialize_i128(self, v: i128) -> Result<()> {
-        self.write(&v.to_string())?;
-        Ok(())
+        if self.strict && i64::try_from(v).is_err() {
+            return Err(out_of_range(v));
+        }
+        self.write(itoa::Buffer::new().format(v))
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
-        self.serialize_u128(u128::from(v))
+        self.serialize_u64(u64::from(v))
     }
 
     fn serialize_u16(self, v: u16) -> Result<()> {
-        self.serialize_u128(u128::from(v))
+        self.serialize_u64(u64::from(v))
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        self.serialize_u128(u128::from(v))
+        self.serialize_u64(u64::from(v))
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.serialize_u128(u128::from(v))
+        if self.strict && i64::try_from(v).is_err() {
+            return Err(out_of_range(v));
+        }
+        self.write(itoa::Buffer::new().format(v))
     }
 
     fn serialize_u128(self, v: u128) -> Result<()> {
-        self.write(&v.to_string())?;
-        Ok(())
+        if self.strict && i64::try_from(v).is_err() {
+            return Err(out_of_range(v));
+        }
+        self.write(itoa::Buffer::new().format(v))
     }
 
     fn serialize_f32(self, v: f32) -> Result<()> {
         self.serialize_f64(f64::from(v))
     }
 
+    // See `format_float`. Strict mode rejects NaN and the infinities
+    // instead, since they can only be written as expressions.
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.write(&v.to_string())?;
-        Ok(())
+        if self.strict && !v.is_finite() {
+            return Err(Error::Message(format!("cannot write {}: Lua has no literal for it", v)));
+        }
+        let v = if self.canonical && v == 0.0 { 0.0 } else { v };
+        self.write(format_float(&mut ryu::Buffer::new(), v))
     }
 
     // Serialize a char as a single-character string.
     fn serialize_char(self, v: char) -> Result<()> {
-        self.serialize_str(&v.to_string())
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        if self.serializing_key && is_identifier(v) {
-            self.write(v)?;
-            return Ok(())
-        }
-        if self.serializing_key {
-            self.write("[")?;
-        }
         self.write("\"")?;
-        for c in v.chars() { 
-            match c {
-                '"' => self.write("\\\"")?,
-                '\'' => self.write("\\'")?,
-                '\x07' => self.write("\\a")?,
-                '\x08' => self.write("\\b")?,
-                '\x12' => self.write("\\f")?,
-                '\n' => self.write("\\n")?,
-                '\r' => self.write("\\r")?,
-                '\t' => self.write("\\t")?,
-                '\x11' => self.write("\\v")?,
-                '\\' => self.write("\\\\")?,
-                '[' => self.write("\\[")?,
-                ']' => self.write("\\]")?,
-                _ => self.write(&c.to_string())?
-            };
-        }
-        self.write("\"")?;
-        if self.serializing_key {
-            self.write("]")?;
-        }
-        Ok(())
+        self.write_escaped(v)?;
+        self.write("\"")
     }
 
     // Serialize a byte array as an array of bytes. Could also use a base64
@@ -287,12 +810,16 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
+        self.nil_context = Some(NIL_SOME);
         value.serialize(self)
     }
 
     // In Serde, unit means an anonymous value containing no data. Map this to
     // LSON as `nil`.
     fn serialize_unit(self) -> Result<()> {
+        if let (true, Some(context)) = (self.strict, self.nil_context) {
+            return Err(Error::Message(context.to_string()));
+        }
         self.write("nil")?;
         Ok(())
     }
@@ -300,7 +827,11 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     // Unit struct means a named value containing no data. Again, since there is
     // no data, map this to LSON as `nil`. There is no need to serialize the
     // name in most formats.
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+    fn serialize_unit_struct(self, name: &'static str) -> Result<()> {
+        if self.constructor_calls {
+            self.start_call(name)?;
+            return self.end_call();
+        }
         self.serialize_unit()
     }
 
@@ -310,10 +841,19 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     // typically use the name.
     fn serialize_unit_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         _variant_index: u32,
         variant: &'static str,
     ) -> Result<()> {
+        if let Some(table) = self.symbols.enum_table(name) {
+            if is_identifier(variant) {
+                return self.write(&format!("{}.{}", table, variant));
+            }
+        }
+        if self.constructor_calls {
+            self.start_call(variant)?;
+            return self.end_call();
+        }
         self.serialize_str(variant)
     }
 
@@ -345,17 +885,24 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
-        self.output.write_all("{ ".as_bytes())?;
+        if self.constructor_calls {
+            self.start_call(variant)?;
+            self.nil_context = None;
+            value.serialize(&mut *self)?;
+            return self.end_call();
+        }
+        self.write("{ ")?;
         if is_identifier(variant) {
-            self.output.write_all(variant.as_bytes())?;
+            self.write(variant)?;
         } else {
-            self.output.write_all("[".as_bytes())?;
+            self.write("[")?;
             variant.serialize(&mut *self)?;
-            self.output.write_all("]".as_bytes())?;
+            self.write("]")?;
         }
-        self.output.write_all(" = ".as_bytes())?;
+        self.write(" = ")?;
+        self.nil_context = Some(NIL_VARIANT_VALUE);
         value.serialize(&mut *self)?;
-        self.output.write_all(" }".as_bytes())?;
+        self.write(" }")?;
         Ok(())
     }
 
@@ -385,9 +932,13 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     // Tuple structs look just like sequences in LSON.
     fn serialize_tuple_struct(
         self,
-        _name: &'static str,
+        name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
+        if self.constructor_calls {
+            self.start_call(name)?;
+            return Ok(self);
+        }
         self.serialize_seq(Some(len))
     }
 
@@ -400,16 +951,20 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.output.write_all("{ ".as_bytes())?;
+        if self.constructor_calls {
+            self.start_call(variant)?;
+            return Ok(self);
+        }
+        self.write("{ ")?;
         if is_identifier(variant) {
-            self.output.write_all(variant.as_bytes())?;
+            self.write(variant)?;
         } else {
-            self.output.write_all("[".as_bytes())?;
+            self.write("[")?;
             variant.serialize(&mut *self)?;
-            self.output.write_all("]".as_bytes())?;
+            self.write("]")?;
         }
 
-        self.output.write_all(" = ".as_bytes())?;
+        self.write(" = ")?;
         self.start_table()?;
         Ok(self)
     }
@@ -417,6 +972,13 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     // Maps are represented in LSON as `{ K = V, K = V, ... }`.
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
         self.start_table()?;
+        self.items.push(1);
+        if self.holds_entries() {
+            self.entries.push(Vec::new());
+        }
+        if self.strict {
+            self.keys.push(HashSet::new());
+        }
         Ok(self)
     }
 
@@ -424,10 +986,15 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
     // serialize the field names of the struct.
     fn serialize_struct(
         self,
-        _name: &'static str,
-        len: usize,
+        name: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeStruct> {
-        self.serialize_map(Some(len))
+        if self.constructor_calls {
+            self.write_constructor(name)?;
+            self.write(" ")?;
+        }
+        self.start_table()?;
+        Ok(self)
     }
 
     // Struct variants are represented in LSON as `{ NAME = { K = V, ... } }`.
@@ -439,15 +1006,21 @@ impl<'a, 'b, W: Write> ser::Serializer for &'a mut Serializer<'b, W> {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.output.write_all("{ ".as_bytes())?;
+        if self.constructor_calls {
+            self.write_constructor(variant)?;
+            self.write(" ")?;
+            self.start_table()?;
+            return Ok(self);
+        }
+        self.write("{ ")?;
         if is_identifier(variant) {
-            self.output.write_all(variant.as_bytes())?;
+            self.write(variant)?;
         } else {
-            self.output.write_all("[".as_bytes())?;
+            self.write("[")?;
             variant.serialize(&mut *self)?;
-            self.output.write_all("]".as_bytes())?;
+            self.write("]")?;
         }
-        self.output.write_all(" = ".as_bytes())?;
+        self.write(" = ")?;
         self.start_table()?;
         Ok(self)
     }
@@ -469,23 +1042,9 @@ impl<'a, 'b, W: Write> ser::SerializeSeq for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
-        if !self.is_table_start() {
-            if self.enable_indent() {
-                self.write(",\n")?;
-            } else {
-                self.write(", ")?;
-            }
-        } else {
-            if self.enable_indent() {
-                self.write("\n")?;
-            } else {
-                self.write(" ")?;
-            }
-            self.clear_table_start();
-        }
-        self.write_indent()?;
-        value.serialize(&mut **self);
-        Ok(())
+        self.start_entry()?;
+        self.nil_context = Some(NIL_ITEM);
+        value.serialize(&mut **self)
     }
 
     // Close the sequence.
@@ -506,23 +1065,9 @@ impl<'a, 'b, W: Write> ser::SerializeTuple for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
-        if !self.is_table_start() {
-            if self.enable_indent() {
-                self.write(",\n")?;
-            } else {
-                self.write(", ")?;
-            }
-        } else {
-            if self.enable_indent() {
-                self.write("\n")?;
-            } else {
-                self.write(" ")?;
-            }
-            self.clear_table_start();
-        }
-        self.write_indent()?;
-        value.serialize(&mut **self);
-        Ok(())
+        self.start_entry()?;
+        self.nil_context = Some(NIL_ITEM);
+        value.serialize(&mut **self)
     }
 
     // Close the sequence.
@@ -542,28 +1087,21 @@ impl<'a, 'b, W: Write> ser::SerializeTupleStruct for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
-        if !self.is_table_start() {
-            if self.enable_indent() {
-                self.write(",\n")?;
-            } else {
-                self.write(", ")?;
-            }
-        } else {
-            if self.enable_indent() {
-                self.write("\n")?;
-            } else {
-                self.write(" ")?;
-            }
-            self.clear_table_start();
+        if self.constructor_calls {
+            self.write_argument_separator()?;
+            self.nil_context = None;
+            return value.serialize(&mut **self);
         }
-        self.write_indent()?;
-        value.serialize(&mut **self);
-        Ok(())
+        self.start_entry()?;
+        self.nil_context = Some(NIL_ITEM);
+        value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<()> {
+        if self.constructor_calls {
+            return self.end_call();
+        }
         self.end_table()?;
-        self.output.write_all("}".as_bytes())?;
         Ok(())
     }
 }
@@ -585,26 +1123,20 @@ impl<'a, 'b, W: Write> ser::SerializeTupleVariant for &'a mut Serializer<'b, W>
     where
         T: ?Sized + Serialize,
     {
-        if !self.is_table_start() {
-            if self.enable_indent() {
-                self.write(",\n")?;
-            } else {
-                self.write(", ")?;
-            }
-        } else {
-            if self.enable_indent() {
-                self.write("\n")?;
-            } else {
-                self.write(" ")?;
-            }
-            self.clear_table_start();
+        if self.constructor_calls {
+            self.write_argument_separator()?;
+            self.nil_context = None;
+            return value.serialize(&mut **self);
         }
-        self.write_indent()?;
-        value.serialize(&mut **self);
-        Ok(())
+        self.start_entry()?;
+        self.nil_context = Some(NIL_ITEM);
+        value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<()> {
+        if self.constructor_calls {
+            return self.end_call();
+        }
         self.end_table()?;
         self.write("}")?;
         Ok(())
@@ -627,39 +1159,21 @@ impl<'a, 'b, W: Write> ser::SerializeMap for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
-        if !self.is_table_start() {
-            if self.enable_indent() {
-                self.write(",\n")?;
-            } else {
-                self.write(", ")?;
+        if self.holds_entries() {
+            let (text, written) = self.capture(|serializer| key.serialize(MapKeySerializer { serializer }));
+            written?;
+            let key = self.sort_key.take().unwrap();
+            self.check_duplicate(&key)?;
+            if let Some(entries) = self.entries.last_mut() {
+                entries.push(Entry { key, text, value: Vec::new() });
             }
-        } else {
-            if self.enable_indent() {
-                self.write("\n")?;
-            } else {
-                self.write(" ")?;
-            }
-            self.clear_table_start();
+            return Ok(());
         }
-
-        self.write_indent()?;
-
-        let current_indent = self.enable_indent;
-        self.enable_indent = false;
-        let ser_type = crate::type_ser::compute_type(&key)?;
-        if ser_type == crate::type_ser::Type::String {
-            self.serializing_key = true;
-
-            key.serialize(&mut **self)?;
-
-            self.serializing_key = false;
-        } else {
-            self.write("[")?;
-            key.serialize(&mut **self)?;
-            self.write("]")?;
+        self.start_entry()?;
+        key.serialize(MapKeySerializer { serializer: &mut **self })?;
+        if let Some(key) = self.sort_key.take() {
+            self.check_duplicate(&key)?;
         }
-        self.enable_indent = current_indent;
-
         Ok(())
     }
 
@@ -670,12 +1184,31 @@ impl<'a, 'b, W: Write> ser::SerializeMap for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
-        self.output.write_all(" = ".as_bytes())?;
-        value.serialize(&mut **self);
-        Ok(())
+        self.nil_context = Some(NIL_MAP_VALUE);
+        if self.holds_entries() {
+            let (text, written) = self.capture(|serializer| value.serialize(serializer));
+            written?;
+            if let Some(entry) = self.entries.last_mut().and_then(|entries| entries.last_mut()) {
+                entry.value = text;
+            }
+            return Ok(());
+        }
+        if !self.positional_key {
+            self.write(" = ")?;
+        }
+        self.positional_key = false;
+        value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<()> {
+        self.items.pop();
+        if self.strict {
+            self.keys.pop();
+        }
+        if self.holds_entries() {
+            let entries = self.entries.pop().unwrap_or_default();
+            self.write_sorted(entries)?;
+        }
         self.end_table()?;
         Ok(())
     }
@@ -691,32 +1224,18 @@ impl<'a, 'b, W: Write> ser::SerializeStruct for &'a mut Serializer<'b, W> {
     where
         T: ?Sized + Serialize,
     {
-        if !self.is_table_start() {
-            if self.enable_indent() {
-                self.write(",\n")?;
-            } else {
-                self.write(", ")?;
-            }
-        } else {
-            if self.enable_indent() {
-                self.write("\n")?;
-            } else {
-                self.write(" ")?;
-            }
-            self.clear_table_start();
-        }
-
-        self.write_indent()?;
+        self.start_entry()?;
 
         if is_identifier(key) {
-            self.output.write_all(key.as_bytes())?;
+            self.write(key)?;
         } else {
-            self.output.write_all("[".as_bytes())?;
+            self.write("[")?;
             key.serialize(&mut **self)?;
-            self.output.write_all("]".as_bytes())?;
+            self.write("]")?;
         }
 
-        self.output.write_all(" = ".as_bytes())?;
+        self.write(" = ")?;
+        self.nil_context = None;
         value.serialize(&mut **self)
     }
 
@@ -736,38 +1255,777 @@ impl<'a, 'b, W: Write> ser::SerializeStructVariant for &'a mut Serializer<'b, W>
     where
         T: ?Sized + Serialize,
     {
-        if !self.is_table_start() {
-            if self.enable_indent() {
-                self.write(",\n")?;
-            } else {
-                self.write(", ")?;
-            }
-        } else {
-            if self.enable_indent() {
-                self.write("\n")?;
-            } else {
-                self.write(" ")?;
-            }
-            self.clear_table_start();
-        }
-
-        self.write_indent()?;
+        self.start_entry()?;
 
         if is_identifier(key) {
-            self.output.write_all(key.as_bytes())?;
+            self.write(key)?;
         } else {
-            self.output.write_all("[".as_bytes())?;
+            self.write("[")?;
             key.serialize(&mut **self)?;
-            self.output.write_all("]".as_bytes())?;
+            self.write("]")?;
         }
 
-        self.output.write_all(" = ".as_bytes())?;
+        self.write(" = ")?;
+        self.nil_context = None;
         value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<()> {
         self.end_table()?;
-        self.output.write_all("}".as_bytes())?;
+        if self.constructor_calls {
+            return Ok(());
+        }
+        self.write("}")?;
         Ok(())
     }
 }
+
+// Writes a map key in a single pass: strings as bare identifiers where they
+// can be, and other scalars as bracketed literals like `[1]` or `[true]`.
+// Keys without a Lua literal, such as tables and nil, are an error.
+struct MapKeySerializer<'s, 'a, W: Write> {
+    serializer: &'s mut Serializer<'a, W>
+}
+
+impl<'s, 'a, W: Write> MapKeySerializer<'s, 'a, W> {
+    fn bracketed<F>(self, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut Serializer<'a, W>) -> Result<()>
+    {
+        self.serializer.write("[")?;
+        write(self.serializer)?;
+        self.serializer.write("]")
+    }
+
+    // Writes an integer key. With compact arrays on it's written as a
+    // positional item if it's the next index, though maps that hold their
+    // entries back leave that until they're sorted.
+    fn integer(self, key: i128) -> Result<()> {
+        let serializer = self.serializer;
+        if serializer.holds_entries() || serializer.strict {
+            serializer.sort_key = Some(SortKey::Integer(key));
+        }
+        if let (false, true, Some(next)) = (serializer.holds_entries(), serializer.compact_arrays, serializer.items.last_mut()) {
+            if key == *next {
+                *next += 1;
+                serializer.positional_key = true;
+                return Ok(());
+            }
+        }
+        serializer.write("[")?;
+        ser::Serializer::serialize_i128(&mut *serializer, key)?;
+        serializer.write("]")
+    }
+
+    fn set_sort_key<F: FnOnce() -> SortKey>(&mut self, key: F) {
+        if self.serializer.holds_entries() || self.serializer.strict {
+            self.serializer.sort_key = Some(key());
+        }
+    }
+}
+
+// A map entry held back until it can be sorted.
+struct Entry {
+    key: SortKey,
+    text: Vec<u8>,
+    value: Vec<u8>
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum SortKey {
+    Integer(i128),
+    Float(f64),
+    String(String),
+    Boolean(bool)
+}
+
+impl SortKey {
+    fn rank(&self) -> u8 {
+        match self {
+            SortKey::Integer(_) | SortKey::Float(_) => 0,
+            SortKey::String(_) => 1,
+            SortKey::Boolean(_) => 2
+        }
+    }
+}
+
+// Numbers come first by value, then strings bytewise, then booleans. Keys
+// that still compare equal, like huge integers that round to the same
+// float, are ordered by their text.
+fn compare_entries(a: &Entry, b: &Entry) -> Ordering {
+    let order = match (&a.key, &b.key) {
+        (SortKey::Integer(x), SortKey::Integer(y)) => x.cmp(y),
+        (SortKey::Float(x), SortKey::Float(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+        (SortKey::Integer(x), SortKey::Float(y)) => (*x as f64).partial_cmp(y).unwrap_or(Ordering::Equal),
+        (SortKey::Float(x), SortKey::Integer(y)) => x.partial_cmp(&(*y as f64)).unwrap_or(Ordering::Equal),
+        (SortKey::String(x), SortKey::String(y)) => x.cmp(y),
+        (SortKey::Boolean(x), SortKey::Boolean(y)) => x.cmp(y),
+        (x, y) => x.rank().cmp(&y.rank())
+    };
+    order.then_with(|| a.text.cmp(&b.text))
+}
+
+// Whether sorted `entries` have exactly the keys 0, 1, 2, ...
+fn counts_from_zero(entries: &[Entry]) -> bool {
+    entries.iter().zip(0..).all(|(entry, index)| entry.key == SortKey::Integer(index))
+}
+
+const NIL_ITEM: &str = "cannot write nil as a list item, since Lua would end the list there";
+const NIL_MAP_VALUE: &str = "cannot write nil as a map value, since Lua would drop the entry";
+const NIL_VARIANT_VALUE: &str = "cannot write nil as the value of a variant, since Lua would drop the variant";
+const NIL_SOME: &str = "cannot write Some(nil), since it would read back as None";
+
+fn out_of_range<N: std::fmt::Display>(n: N) -> Error {
+    Error::Message(format!("cannot write {}: it doesn't fit in a Lua integer", n))
+}
+
+fn key_error(kind: &str) -> Error {
+    Error::Message(format!("cannot use {} as a table key", kind))
+}
+
+impl<'s, 'a, W: Write> ser::Serializer for MapKeySerializer<'s, 'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = ser::Impossible<(), Error>;
+    type SerializeTuple = ser::Impossible<(), Error>;
+    type SerializeTupleStruct = ser::Impossible<(), Error>;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = ser::Impossible<(), Error>;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_bool(mut self, v: bool) -> Result<()> {
+        self.set_sort_key(|| SortKey::Boolean(v));
+        self.bracketed(|serializer| ser::Serializer::serialize_bool(serializer, v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.integer(i128::from(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.serialize_u64(u64::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.integer(i128::from(v))
+    }
+
+    fn serialize_u128(mut self, v: u128) -> Result<()> {
+        match i128::try_from(v) {
+            Ok(v) => self.integer(v),
+            Err(_) => {
+                self.set_sort_key(|| SortKey::Float(v as f64));
+                self.bracketed(|serializer| ser::Serializer::serialize_u128(serializer, v))
+            }
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    // Canonical maps write integral float keys as the integers Lua
+    // normalizes them to.
+    fn serialize_f64(mut self, v: f64) -> Result<()> {
+        if v.is_nan() {
+            return Err(key_error("NaN"));
+        }
+        if self.serializer.canonical && v.fract() == 0.0 && v >= i128::MIN as f64 && v < i128::MAX as f64 {
+            return self.integer(v as i128);
+        }
+        self.set_sort_key(|| SortKey::Float(v));
+        self.bracketed(|serializer| ser::Serializer::serialize_f64(serializer, v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(mut self, v: &str) -> Result<()> {
+        self.set_sort_key(|| SortKey::String(v.to_string()));
+        self.serializer.write_str_key(v)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<()> {
+        Err(key_error("a byte array"))
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        Err(key_error("nil"))
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Err(key_error("nil"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Err(key_error("nil"))
+    }
+
+    // Variants of enums registered in `symbols` are written as `[Enum.Name]`,
+    // and other unit variants as strings.
+    fn serialize_unit_variant(
+        mut self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        if let Some(table) = self.serializer.symbols.enum_table(name) {
+            if is_identifier(variant) {
+                let symbol = format!("{}.{}", table, variant);
+                self.set_sort_key(|| SortKey::String(symbol.clone()));
+                return self.serializer.write(&format!("[{}]", symbol));
+            }
+        }
+        self.set_sort_key(|| SortKey::String(variant.to_string()));
+        self.serializer.write_str_key(variant)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(key_error("a table"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(key_error("a table"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(key_error("a table"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(key_error("a table"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(key_error("a table"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Err(key_error("a table"))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct> {
+        Err(key_error("a table"))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(key_error("a table"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Label {
+        name: String,
+        address: u32
+    }
+
+    #[derive(Serialize)]
+    struct Point(i32, i32);
+
+    #[derive(Serialize)]
+    struct Marker;
+
+    #[derive(Serialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Line(Point, Point),
+        Rect { width: u32, height: u32 }
+    }
+
+    fn with<T: Serialize, F>(value: &T, configure: F) -> Result<String>
+    where
+        F: FnOnce(&mut Serializer<Vec<u8>>)
+    {
+        let mut output = Vec::new();
+        let mut serializer = Serializer::new(&mut output);
+        configure(&mut serializer);
+        value.serialize(&mut serializer)?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    fn calls<T: Serialize>(value: &T) -> String {
+        with(value, |serializer| serializer.set_constructor_calls(true)).unwrap()
+    }
+
+    #[test]
+    fn constructor_calls() {
+        let label = Label { name: "start".to_string(), address: 16 };
+        assert_eq!(calls(&label), "Label { name = \"start\", address = 16 }");
+        assert_eq!(calls(&Point(1, -2)), "Point(1, -2)");
+        assert_eq!(calls(&Marker), "Marker()");
+        let shapes = vec![
+            Shape::Empty,
+            Shape::Circle(1.5),
+            Shape::Line(Point(0, 0), Point(1, 1)),
+            Shape::Rect { width: 2, height: 3 }
+        ];
+        assert_eq!(calls(&shapes),
+            "{ Empty(), Circle(1.5), Line(Point(0, 0), Point(1, 1)), Rect { width = 2, height = 3 } }");
+    }
+
+    #[test]
+    fn tagged_tables() {
+        let shapes = vec![Shape::Empty, Shape::Circle(1.5), Shape::Rect { width: 2, height: 3 }];
+        assert_eq!(to_string(&shapes).unwrap(),
+            "{ \"Empty\", { Circle = 1.5 }, { Rect = { width = 2, height = 3 }} }");
+        assert_eq!(to_string(&Point(1, 2)).unwrap(), "{ 1, 2 }");
+    }
+
+    #[test]
+    fn symbols() {
+        #[derive(Serialize, serde::Deserialize)]
+        enum Align {
+            Left,
+            Center
+        }
+        let mut symbols = Symbols::new();
+        symbols.add_enum::<Align>("Align").unwrap();
+        let text = with(&vec![Align::Left, Align::Center], |serializer| serializer.set_symbols(symbols)).unwrap();
+        assert_eq!(text, "{ Align.Left, Align.Center }");
+        assert_eq!(to_string(&vec![Align::Left]).unwrap(), "{ \"Left\" }");
+    }
+
+    #[test]
+    fn constructor_round_trip() {
+        #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+        enum Entry {
+            Label { name: String, address: u32 },
+            Alias(String, String)
+        }
+        let entries = vec![
+            Entry::Label { name: "a".to_string(), address: 1 },
+            Entry::Alias("b".to_string(), "a".to_string())
+        ];
+        let text = calls(&entries);
+        assert_eq!(crate::de::from_str::<Vec<Entry>>(&text).unwrap(), entries);
+    }
+
+    fn records(style: RecordStyle) -> String {
+        let mut writer = RecordWriter::new(Vec::new(), style);
+        writer.write(&Label { name: "a".to_string(), address: 1 }).unwrap();
+        writer.write(&Label { name: "b".to_string(), address: 2 }).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn record_styles() {
+        assert_eq!(records(RecordStyle::Table),
+            "{ name = \"a\", address = 1 }\n{ name = \"b\", address = 2 }\n");
+        assert_eq!(records(RecordStyle::Constructor),
+            "Label { name = \"a\", address = 1 }\nLabel { name = \"b\", address = 2 }\n");
+        assert_eq!(records(RecordStyle::Append("log".to_string())),
+            "log[#log+1] = { name = \"a\", address = 1 }\nlog[#log+1] = { name = \"b\", address = 2 }\n");
+        let mut writer = RecordWriter::new(Vec::new(), RecordStyle::Append("a.b".to_string()));
+        assert!(writer.write(&1).is_err());
+    }
+
+    #[test]
+    fn records_round_trip() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Label {
+            name: String,
+            address: u32
+        }
+        let expected = vec![
+            Label { name: "a".to_string(), address: 1 },
+            Label { name: "b".to_string(), address: 2 }
+        ];
+        for style in [RecordStyle::Table, RecordStyle::Constructor, RecordStyle::Append("log".to_string())] {
+            let text = records(style.clone());
+            let streamed: Vec<Label> = crate::de::Deserializer::from_str(&text)
+                .into_iter().collect::<Result<_>>().unwrap();
+            assert_eq!(streamed, expected);
+            if style != RecordStyle::Table {
+                assert_eq!(crate::de::from_str::<Vec<Label>>(&text).unwrap(), expected);
+            }
+        }
+    }
+
+    fn events<F: FnOnce(&mut EventWriter<Vec<u8>>) -> Result<()>>(write: F) -> Result<String> {
+        let mut output = Vec::new();
+        write(&mut EventWriter::new(&mut output))?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn event_writer() {
+        let text = events(|writer| {
+            writer.begin_table()?;
+            writer.key_str("name")?;
+            writer.string("a")?;
+            writer.key_int(2)?;
+            writer.boolean(true)?;
+            writer.positional()?;
+            writer.number(1.0)?;
+            writer.end_table()
+        }).unwrap();
+        assert_eq!(text, "{ name = \"a\", [2] = true, 1.0 }");
+        assert!(events(|writer| writer.key_str("name")).is_err());
+        assert!(events(|writer| {
+            writer.begin_table()?;
+            writer.nil()
+        }).is_err());
+    }
+
+    #[test]
+    fn event_writer_floats() {
+        let text = events(|writer| {
+            writer.begin_table()?;
+            for n in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.5, 1e100] {
+                writer.positional()?;
+                writer.number(*n)?;
+            }
+            writer.end_table()
+        }).unwrap();
+        assert_eq!(text, "{ 0/0, 1/0, -1/0, -0.5, 1e100 }");
+    }
+
+    #[test]
+    fn floats() {
+        let floats = vec![0.5, 1.0, -0.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY];
+        let text = to_string(&floats).unwrap();
+        assert_eq!(text, "{ 0.5, 1.0, -0.0, 0/0, 1/0, -1/0 }");
+        let read: Vec<f64> = crate::de::from_str(&text).unwrap();
+        assert_eq!(read[..3], floats[..3]);
+        assert!(read[3].is_nan());
+        assert_eq!(read[4..], floats[4..]);
+        assert_eq!(to_string_canonical(&-0.0).unwrap(), "0.0");
+        let mut keys = BTreeMap::new();
+        keys.insert("inf", f64::INFINITY);
+        assert_eq!(to_string(&keys).unwrap(), "{ inf = 1/0 }");
+    }
+
+    #[test]
+    fn strict_floats() {
+        let strict = |value: f64| with(&value, |serializer| serializer.set_strict(true));
+        assert_eq!(strict(1.5).unwrap(), "1.5");
+        assert!(strict(f64::NAN).is_err());
+        assert!(strict(f64::NEG_INFINITY).is_err());
+    }
+
+    // A map with the single entry `key => 1`.
+    struct KeyOf<K>(K);
+
+    impl<K: Serialize> Serialize for KeyOf<K> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            serializer.collect_map(std::iter::once((&self.0, 1)))
+        }
+    }
+
+    fn key<K: Serialize>(key: K) -> Result<String> {
+        to_string(&KeyOf(key))
+    }
+
+    #[test]
+    fn map_keys() {
+        #[derive(Serialize)]
+        struct Id(u32);
+        assert_eq!(key("name").unwrap(), "{ name = 1 }");
+        assert_eq!(key("x y").unwrap(), "{ [\"x y\"] = 1 }");
+        assert_eq!(key("end").unwrap(), "{ [\"end\"] = 1 }");
+        assert_eq!(key('c').unwrap(), "{ c = 1 }");
+        assert_eq!(key(42u8).unwrap(), "{ [42] = 1 }");
+        assert_eq!(key(-7i64).unwrap(), "{ [-7] = 1 }");
+        assert_eq!(key(true).unwrap(), "{ [true] = 1 }");
+        assert_eq!(key(1.5).unwrap(), "{ [1.5] = 1 }");
+        assert_eq!(key(Id(3)).unwrap(), "{ [3] = 1 }");
+        assert_eq!(key(Shape::Empty).unwrap(), "{ Empty = 1 }");
+    }
+
+    #[test]
+    fn invalid_map_keys() {
+        assert!(key((1, 2)).is_err());
+        assert!(key(vec![1]).is_err());
+        assert!(key(Option::<u8>::None).is_err());
+        assert!(key(()).is_err());
+        assert!(key(f64::NAN).is_err());
+        assert!(key(Shape::Circle(1.0)).is_err());
+        assert!(key(Label { name: "a".to_string(), address: 1 }).is_err());
+    }
+
+    #[test]
+    fn keys_serialize_once() {
+        use std::cell::Cell;
+
+        struct Counted<'c>(&'c Cell<usize>);
+
+        impl<'c> Serialize for Counted<'c> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                self.0.set(self.0.get() + 1);
+                serializer.serialize_str("key")
+            }
+        }
+
+        let count = Cell::new(0);
+        assert_eq!(key(Counted(&count)).unwrap(), "{ key = 1 }");
+        assert_eq!(count.get(), 1);
+    }
+
+    fn compact<T: Serialize>(value: &T, zero_based: bool) -> String {
+        with(value, |serializer| {
+            serializer.set_compact_arrays(true);
+            serializer.set_zero_based_arrays(zero_based);
+        }).unwrap()
+    }
+
+    fn read_zero_based<T: serde::de::DeserializeOwned>(text: &str) -> T {
+        let mut deserializer = crate::de::Deserializer::from_str(text);
+        deserializer.set_zero_based_arrays(true);
+        let value = T::deserialize(&mut deserializer).unwrap();
+        deserializer.end().unwrap();
+        value
+    }
+
+    #[test]
+    fn compact_arrays() {
+        let map: BTreeMap<u32, &str> = vec![(1, "a"), (2, "b"), (4, "d")].into_iter().collect();
+        assert_eq!(compact(&map, false), "{ \"a\", \"b\", [4] = \"d\" }");
+        assert_eq!(to_string(&map).unwrap(), "{ [1] = \"a\", [2] = \"b\", [4] = \"d\" }");
+        let read: BTreeMap<u32, String> = crate::de::from_str(&compact(&map, false)).unwrap();
+        assert_eq!(read.keys().collect::<Vec<_>>(), vec![&1, &2, &4]);
+    }
+
+    #[test]
+    fn zero_based_arrays() {
+        let map: BTreeMap<u32, &str> = vec![(0, "a"), (1, "b"), (2, "c")].into_iter().collect();
+        let text = compact(&map, true);
+        assert_eq!(text, "{ \"a\", \"b\", \"c\" }");
+        assert_eq!(read_zero_based::<BTreeMap<u32, String>>(&text).len(), 3);
+        assert_eq!(read_zero_based::<BTreeMap<u32, String>>(&text)[&0], "a");
+        assert_eq!(crate::de::from_str::<Vec<String>>(&text).unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn zero_based_arrays_with_gaps() {
+        // Only a whole list from 0 is shifted, so that each key reads back
+        // as the same key.
+        let map: BTreeMap<u32, &str> = vec![(0, "a"), (1, "b"), (3, "d")].into_iter().collect();
+        let text = compact(&map, true);
+        assert_eq!(text, "{ [0] = \"a\", [1] = \"b\", [3] = \"d\" }");
+        let read: BTreeMap<u32, String> = read_zero_based(&text);
+        assert_eq!(read.keys().collect::<Vec<_>>(), vec![&0, &1, &3]);
+
+        let map: BTreeMap<u32, &str> = vec![(1, "b"), (2, "c")].into_iter().collect();
+        assert_eq!(compact(&map, true), "{ [1] = \"b\", [2] = \"c\" }");
+    }
+
+    #[test]
+    fn zero_based_arrays_are_deterministic() {
+        use std::collections::HashMap;
+
+        let keys = [(5, 5), (0, 0), (2, 2), (9, 9)];
+        let hashed: HashMap<i64, i64> = keys.iter().cloned().collect();
+        let sorted: BTreeMap<i64, i64> = keys.iter().cloned().collect();
+        assert_eq!(compact(&hashed, true), "{ [0] = 0, [2] = 2, [5] = 5, [9] = 9 }");
+        assert_eq!(compact(&hashed, true), compact(&sorted, true));
+        let list: HashMap<i64, i64> = (0..20).map(|n| (n, n * 2)).collect();
+        let read: HashMap<i64, i64> = read_zero_based(&compact(&list, true));
+        assert_eq!(read, list);
+    }
+
+    #[test]
+    fn canonical_key_order() {
+        let text = "{ b = 1, [10] = 2, [\"B\"] = 3, [true] = 4, [2] = 5, [-1.5] = 6, a = 7, [false] = 8 }";
+        let value: crate::value::Value = crate::de::from_str(text).unwrap();
+        assert_eq!(to_string_canonical(&value).unwrap(),
+            "{ [-1.5] = 6, [2] = 5, [10] = 2, B = 3, a = 7, b = 1, [false] = 8, [true] = 4 }");
+    }
+
+    #[test]
+    fn canonical_output_is_stable() {
+        use std::collections::HashMap;
+
+        let mut forward = HashMap::new();
+        let mut backward = HashMap::new();
+        for n in 0..50 {
+            forward.insert(format!("key{}", n), vec![n as f64, -0.0]);
+            backward.insert(format!("key{}", 49 - n), vec![(49 - n) as f64, 0.0]);
+        }
+        let text = to_string_canonical(&forward).unwrap();
+        assert_eq!(text, to_string_canonical(&backward).unwrap());
+        assert!(text.starts_with("{ key0 = { 0.0, 0.0 }, key1 = { 1.0, 0.0 }, key10 = "));
+    }
+
+    #[test]
+    fn canonical_sets() {
+        use std::collections::HashSet;
+
+        #[derive(Serialize)]
+        struct Set {
+            #[serde(with = "crate::as_set")]
+            ids: HashSet<u32>
+        }
+
+        // Each set hashes with its own random keys.
+        let forward = Set { ids: (0..50).collect() };
+        let backward = Set { ids: (0..50).rev().collect() };
+        let text = to_string_canonical(&forward).unwrap();
+        assert_eq!(text, to_string_canonical(&backward).unwrap());
+        assert!(text.starts_with("{ ids = { [0] = true, [1] = true, [2] = true, "));
+
+        // Without `as_set`, the set is a list in iteration order.
+        let ids: HashSet<u32> = (0..50).collect();
+        let read: Vec<u32> = crate::de::from_str(&to_string_canonical(&ids).unwrap()).unwrap();
+        assert_eq!(read, ids.iter().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn canonical_numbers_and_strings() {
+        assert_eq!(with(&KeyOf(2.0), |serializer| serializer.set_canonical(true)).unwrap(), "{ [2] = 1 }");
+        assert_eq!(key(2.0).unwrap(), "{ [2.0] = 1 }");
+        assert_eq!(to_string_canonical(&-0.0f32).unwrap(), "0.0");
+        assert_eq!(to_string_canonical(&1e21).unwrap(), "1e21");
+        let text = to_string_canonical(&"a \"b\"\n\t\\").unwrap();
+        assert_eq!(text, "\"a \\\"b\\\"\\n\\t\\\\\"");
+        assert_eq!(crate::de::from_str::<String>(&text).unwrap(), "a \"b\"\n\t\\");
+    }
+
+    #[test]
+    fn canonical_structs_keep_field_order() {
+        let label = Label { name: "a".to_string(), address: 1 };
+        assert_eq!(to_string_canonical(&label).unwrap(), "{ name = \"a\", address = 1 }");
+        let pretty = to_string_pretty(&vec![label]).unwrap();
+        assert!(pretty.contains('\n'));
+        assert!(!to_string_canonical(&vec![Label { name: "a".to_string(), address: 1 }]).unwrap().contains('\n'));
+    }
+
+    fn strict<T: Serialize>(value: &T) -> Result<String> {
+        with(value, |serializer| serializer.set_strict(true))
+    }
+
+    // `{ [1] = 1, [1.0] = 2 }`, which Lua reads as a single entry.
+    struct NormalizedDuplicate;
+
+    impl Serialize for NormalizedDuplicate {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            use serde::ser::SerializeMap;
+            let mut map = serializer.serialize_map(Some(2))?;
+            map.serialize_entry(&1, &1)?;
+            map.serialize_entry(&1.0, &2)?;
+            map.end()
+        }
+    }
+
+    struct Failing;
+
+    impl Serialize for Failing {
+        fn serialize<S: serde::Serializer>(&self, _serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("failed"))
+        }
+    }
+
+    #[test]
+    fn strict_accepts_faithful_data() {
+        let map: BTreeMap<&str, Option<u8>> = vec![("a", Some(1))].into_iter().collect();
+        assert_eq!(strict(&map).unwrap(), "{ a = 1 }");
+        assert_eq!(strict(&Some(Some(1))).unwrap(), "1");
+        assert_eq!(strict(&Label { name: "a".to_string(), address: 1 }).unwrap(), "{ name = \"a\", address = 1 }");
+        assert_eq!(strict(&i64::MIN).unwrap(), "-9223372036854775808");
+    }
+
+    #[test]
+    fn strict_rejects_lossy_data() {
+        let map: BTreeMap<&str, Option<u8>> = vec![("a", None)].into_iter().collect();
+        assert!(strict(&map).is_err());
+        assert!(to_string(&map).is_ok());
+        assert!(strict(&Some(Option::<u8>::None)).is_err());
+        assert!(strict(&vec![Some(1), None]).is_err());
+        assert!(strict(&NormalizedDuplicate).is_err());
+        assert!(to_string(&NormalizedDuplicate).is_ok());
+        assert!(strict(&KeyOf(f64::NAN)).is_err());
+        assert!(strict(&u64::MAX).is_err());
+        assert!(strict(&(i64::MAX as i128 + 1)).is_err());
+    }
+
+    #[test]
+    fn element_errors_propagate() {
+        assert!(to_string(&vec![Failing]).is_err());
+        assert!(strict(&vec![Failing]).is_err());
+        assert!(to_string(&(1, Failing)).is_err());
+        let map: BTreeMap<&str, Failing> = vec![("a", Failing)].into_iter().collect();
+        assert!(to_string(&map).is_err());
+    }
+}
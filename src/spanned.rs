@@ -0,0 +1,195 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Range;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use crate::error::Position;
+
+// The name and fields of the struct that `de::Deserializer` recognizes as a
+// request for a span.
+pub(crate) const NAME: &str = "$serde_lson::Spanned";
+pub(crate) const START: &str = "$serde_lson::Spanned::start";
+pub(crate) const END: &str = "$serde_lson::Spanned::end";
+pub(crate) const VALUE: &str = "$serde_lson::Spanned::value";
+pub(crate) const FIELDS: &[&str] = &[VALUE, START, END];
+
+// A value along with where it was in the LSON source, for reporting problems
+// found after deserializing. Only the LSON deserializer can produce one, and
+// only for values read from the source; comparisons and serialization look
+// at the value alone.
+#[derive(Debug,Clone)]
+pub struct Spanned<T> {
+    start: Position,
+    end: Position,
+    value: T
+}
+
+impl<T> Spanned<T> {
+    // Where the value starts.
+    pub fn start(&self) -> Position {
+        self.start
+    }
+
+    // Just past the end of the value.
+    pub fn end(&self) -> Position {
+        self.end
+    }
+
+    // The byte offsets of the value.
+    pub fn span(&self) -> Range<usize> {
+        self.start.offset..self.end.offset
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Spanned<T>) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for Spanned<T> {}
+
+impl<T: Hash> Hash for Spanned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state)
+    }
+}
+
+impl<T: Serialize> Serialize for Spanned<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Spanned<T>, D::Error> {
+        deserializer.deserialize_struct(NAME, FIELDS, SpannedVisitor { value: PhantomData })
+    }
+}
+
+struct SpannedVisitor<T> {
+    value: PhantomData<T>
+}
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for SpannedVisitor<T> {
+    type Value = Spanned<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a value read from LSON source")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Spanned<T>, A::Error> {
+        let mut start = None;
+        let mut end = None;
+        let mut value = None;
+        while let Some(key) = map.next_key::<&str>()? {
+            match key {
+                START => start = Some(position(map.next_value()?)),
+                END => end = Some(position(map.next_value()?)),
+                VALUE => value = Some(map.next_value()?),
+                key => return Err(de::Error::unknown_field(key, FIELDS))
+            }
+        }
+        match (start, end, value) {
+            (Some(start), Some(end), Some(value)) => Ok(Spanned { start, end, value }),
+            _ => Err(de::Error::custom("the location of the value is not known"))
+        }
+    }
+}
+
+fn position((offset, line, column): (usize, usize, usize)) -> Position {
+    Position { offset, line, column }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use crate::de::{from_reader, from_str};
+    use crate::ser::to_string;
+    use crate::value::Value;
+
+    use super::*;
+
+    #[derive(Debug,Deserialize)]
+    struct Window {
+        title: Spanned<String>,
+        size: Spanned<Vec<Spanned<u32>>>,
+        layout: Layout
+    }
+
+    #[derive(Debug,Deserialize)]
+    struct Layout {
+        margin: Spanned<f64>
+    }
+
+    const WINDOW: &str = "{\n  title = \"main\",\n  size = { 640, 480 },\n  layout = { margin = 1.5 }\n}";
+
+    #[test]
+    fn offsets() {
+        let value: Spanned<u32> = from_str("return  42 ").unwrap();
+        assert_eq!(value.span(), 8..10);
+        assert_eq!(value.into_inner(), 42);
+        let value: Spanned<Vec<i8>> = from_str("{ 1, -2 }").unwrap();
+        assert_eq!(value.span(), 0..9);
+        let value: Vec<Spanned<String>> = from_str("{ 'a', \"b\\n\" }").unwrap();
+        assert_eq!(value.iter().map(Spanned::span).collect::<Vec<_>>(), [2..5, 7..12]);
+    }
+
+    #[test]
+    fn lines_and_columns() {
+        let window: Window = from_str(WINDOW).unwrap();
+        assert_eq!(window.title.get_ref(), "main");
+        assert_eq!(window.title.start(), Position { offset: 12, line: 2, column: 11 });
+        assert_eq!(window.title.end(), Position { offset: 18, line: 2, column: 17 });
+        assert_eq!((window.size.start().line, window.size.start().column), (3, 10));
+        assert_eq!((window.size.end().line, window.size.end().column), (3, 22));
+    }
+
+    #[test]
+    fn nested_fields() {
+        let window: Window = from_str(WINDOW).unwrap();
+        let size = window.size.get_ref();
+        assert_eq!(*size[1].get_ref(), 480);
+        assert_eq!(size[1].span(), 36..39);
+        assert_eq!(*window.layout.margin.get_ref(), 1.5);
+        assert_eq!((window.layout.margin.start().line, window.layout.margin.start().column), (4, 23));
+
+        // Readers count positions the same way.
+        let window: Window = from_reader(WINDOW.as_bytes()).unwrap();
+        assert_eq!(window.size.get_ref()[1].span(), 36..39);
+    }
+
+    #[test]
+    fn value_only() {
+        let a: Spanned<String> = from_str("'text'").unwrap();
+        let b: Spanned<String> = from_str("\n\n  'text'").unwrap();
+        assert_ne!(a.span(), b.span());
+        assert_eq!(a, b);
+        assert_eq!(to_string(&b).unwrap(), to_string(&"text").unwrap());
+        let mut b = b;
+        b.get_mut().push('!');
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn spans_need_source() {
+        let error = Spanned::<u8>::deserialize(Value::Integer(1)).unwrap_err();
+        assert!(error.to_string().contains("a value read from LSON source"), "{}", error);
+    }
+}
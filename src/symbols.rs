@@ -0,0 +1,130 @@
+
+use std::collections::HashMap;
+
+use serde::de::{self, DeserializeOwned, Visitor};
+
+use crate::error::{Error, Result};
+use crate::value::Value;
+
+// Named constants that LSON refers to with dotted names, such as
+// `Align.Center` or `Color.Red`.
+//
+// Constants resolve to their value when deserializing. Registered enums also
+// change serialization: their unit variants are written as `Table.Variant`
+// instead of as strings.
+#[derive(Debug,Clone,Default)]
+pub struct Symbols {
+    constants: HashMap<String, Value>,
+    // Rust enum name to the name of the table its variants live in.
+    enums: HashMap<&'static str, String>
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    // Adds `table.name` for each of `constants`.
+    pub fn add_constants<I, K>(&mut self, table: &str, constants: I)
+    where
+        I: IntoIterator<Item = (K, Value)>,
+        K: AsRef<str>
+    {
+        for (name, value) in constants {
+            self.constants.insert(format!("{}.{}", table, name.as_ref()), value);
+        }
+    }
+
+    // Adds `table.Variant` for each variant of the enum `T`, resolving to the
+    // variant's name, and writes unit variants of `T` that way.
+    pub fn add_enum<T: DeserializeOwned>(&mut self, table: &str) -> Result<()> {
+        let mut probe = EnumProbe { found: None };
+        let _ = T::deserialize(&mut probe);
+        let (name, variants) = probe.found
+            .ok_or_else(|| Error::Message(format!("cannot register '{}': not an enum", table)))?;
+        for variant in variants {
+            self.constants.insert(format!("{}.{}", table, variant), Value::String(variant.to_string()));
+        }
+        self.enums.insert(name, table.to_string());
+        Ok(())
+    }
+
+    // Looks up a dotted name like `Align.Center`.
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        self.constants.get(path)
+    }
+
+    // The table that unit variants of the Rust enum `name` are written in.
+    pub fn enum_table(&self, name: &str) -> Option<&str> {
+        self.enums.get(name).map(|table| table.as_str())
+    }
+}
+
+// Captures the name and variants an enum passes to `deserialize_enum`; every
+// other request fails.
+struct EnumProbe {
+    found: Option<(&'static str, &'static [&'static str])>
+}
+
+impl<'de> de::Deserializer<'de> for &mut EnumProbe {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::Message("not an enum".to_string()))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        _visitor: V
+    ) -> Result<V::Value> {
+        self.found = Some((name, variants));
+        Err(Error::Message("not an enum".to_string()))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Align {
+        Left,
+        Center,
+        Right
+    }
+
+    #[test]
+    fn enums() {
+        let mut symbols = Symbols::new();
+        symbols.add_enum::<Align>("Align").unwrap();
+        assert_eq!(symbols.get("Align.Center"), Some(&Value::String("Center".to_string())));
+        assert_eq!(symbols.get("Align.Top"), None);
+        assert_eq!(symbols.enum_table("Align"), Some("Align"));
+    }
+
+    #[test]
+    fn not_an_enum() {
+        let mut symbols = Symbols::new();
+        let error = symbols.add_enum::<String>("Name").unwrap_err();
+        assert_eq!(error, Error::Message("cannot register 'Name': not an enum".to_string()));
+    }
+
+    #[test]
+    fn constants() {
+        let mut symbols = Symbols::new();
+        symbols.add_constants("Color", vec![("Red", Value::Integer(0xff0000)), ("Black", Value::Integer(0))]);
+        assert_eq!(symbols.get("Color.Red"), Some(&Value::Integer(0xff0000)));
+        assert_eq!(symbols.get("Red"), None);
+    }
+}
@@ -1,13 +1,549 @@
 
+#[cfg(not(feature = "preserve_order"))]
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "preserve_order")]
+use indexmap::IndexMap;
+
+use crate::error::{Error, Result};
+
+// The hash part of a table. With the `preserve_order` feature it keeps
+// entries in the order they were inserted, which for a parsed table is
+// source order.
+#[cfg(not(feature = "preserve_order"))]
+pub type HashPart = HashMap<Key, Value>;
+#[cfg(feature = "preserve_order")]
+pub type HashPart = IndexMap<Key, Value>;
+
+// An LSON value as Lua sees it.
+#[derive(Debug,PartialEq,Clone)]
 pub enum Value {
     Nil,
     Boolean(bool),
     Integer(i128),
     Float(f64),
     String(String),
-    Table {
-        
+    Table(Table)
+}
+
+// A Lua table. Like in Lua, the entries with keys `1..=n` live in an array
+// part and everything else in a hash part.
+#[derive(Debug,PartialEq,Clone,Default)]
+pub struct Table {
+    array: Vec<Value>,
+    hash: HashPart
+}
+
+// A table key. Floats with an integral value are normalized to integers,
+// so `[1.0]` and `[1]` name the same entry.
+#[derive(Debug,Clone)]
+pub enum Key {
+    Boolean(bool),
+    Integer(i128),
+    Float(f64),
+    String(String)
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) | Value::Float(_) => "number",
+            Value::String(_) => "string",
+            Value::Table(_) => "table"
+        }
+    }
+
+    // Converts this value into a table key, or returns `None` for values
+    // that Lua doesn't allow as keys (`nil` and NaN). Tables are not
+    // supported as keys.
+    pub fn into_key(self) -> Option<Key> {
+        match self {
+            Value::Boolean(b) => Some(Key::Boolean(b)),
+            Value::Integer(i) => Some(Key::Integer(i)),
+            Value::Float(f) => Key::from_float(f),
+            Value::String(s) => Some(Key::String(s)),
+            Value::Nil | Value::Table(_) => None
+        }
+    }
+}
+
+impl Key {
+    fn from_float(f: f64) -> Option<Key> {
+        if f.is_nan() {
+            None
+        } else if f.fract() == 0.0 && f >= i128::MIN as f64 && f < i128::MAX as f64 {
+            Some(Key::Integer(f as i128))
+        } else {
+            Some(Key::Float(f))
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        match (self, other) {
+            (Key::Boolean(a), Key::Boolean(b)) => a == b,
+            (Key::Integer(a), Key::Integer(b)) => a == b,
+            (Key::Float(a), Key::Float(b)) => a.to_bits() == b.to_bits(),
+            (Key::String(a), Key::String(b)) => a == b,
+            _ => false
+        }
+    }
+}
+
+// Float keys are never NaN or integral (see `Key::from_float`), so comparing
+// their bits is the same as comparing their values.
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Key::Boolean(b) => b.hash(state),
+            Key::Integer(i) => i.hash(state),
+            Key::Float(f) => f.to_bits().hash(state),
+            Key::String(s) => s.hash(state)
+        }
+    }
+}
+
+// Writes the key the way it would appear in brackets, as in `["name"]`.
+impl fmt::Display for Key {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Boolean(b) => write!(formatter, "[{}]", b),
+            Key::Integer(i) => write!(formatter, "[{}]", i),
+            Key::Float(f) => write!(formatter, "[{}]", f),
+            Key::String(s) => write!(formatter, "[{:?}]", s)
+        }
+    }
+}
+
+impl From<Key> for Value {
+    fn from(key: Key) -> Value {
+        match key {
+            Key::Boolean(b) => Value::Boolean(b),
+            Key::Integer(i) => Value::Integer(i),
+            Key::Float(f) => Value::Float(f),
+            Key::String(s) => Value::String(s)
+        }
+    }
+}
+
+impl Table {
+    pub fn new() -> Table {
+        Table::default()
+    }
+
+    // The number of entries in the array part.
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty() && self.hash.is_empty()
+    }
+
+    pub fn array(&self) -> &[Value] {
+        &self.array
+    }
+
+    pub fn hash(&self) -> &HashPart {
+        &self.hash
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        match self.array_index(key) {
+            Some(idx) => self.array.get(idx),
+            None => self.hash.get(key)
+        }
+    }
+
+    // Sets `key` to `value`. Unlike Lua, `nil` values are kept in the array
+    // part so that lists of optional values keep their length.
+    pub fn insert(&mut self, key: Key, value: Value) {
+        if let Some(idx) = self.array_index(&key) {
+            self.array[idx] = value;
+        } else if key == Key::Integer(self.array.len() as i128 + 1) {
+            self.array.push(value);
+            // Entries that now continue the array move out of the hash part.
+            while let Some(next) = self.remove_hashed(&Key::Integer(self.array.len() as i128 + 1)) {
+                self.array.push(next);
+            }
+        } else if value == Value::Nil {
+            self.remove_hashed(&key);
+        } else {
+            self.hash.insert(key, value);
+        }
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.insert(Key::Integer(self.array.len() as i128 + 1), value);
+    }
+
+    // All entries, starting with the array part in order.
+    pub fn iter(&self) -> impl Iterator<Item = (Key, &Value)> {
+        self.array.iter()
+            .enumerate()
+            .map(|(idx, value)| (Key::Integer(idx as i128 + 1), value))
+            .chain(self.hash.iter().map(|(key, value)| (key.clone(), value)))
+    }
+
+    // Removes an entry from the hash part, keeping the others in order.
+    #[cfg(not(feature = "preserve_order"))]
+    fn remove_hashed(&mut self, key: &Key) -> Option<Value> {
+        self.hash.remove(key)
+    }
+
+    #[cfg(feature = "preserve_order")]
+    fn remove_hashed(&mut self, key: &Key) -> Option<Value> {
+        self.hash.shift_remove(key)
+    }
+
+    fn array_index(&self, key: &Key) -> Option<usize> {
+        match key {
+            Key::Integer(i) if *i >= 1 && *i <= self.array.len() as i128 => Some(*i as usize - 1),
+            _ => None
+        }
+    }
+
+    fn into_entries(self) -> impl Iterator<Item = (Value, Value)> {
+        self.array.into_iter()
+            .enumerate()
+            .map(|(idx, value)| (Value::Integer(idx as i128 + 1), value))
+            .chain(self.hash.into_iter().map(|(key, value)| (Value::from(key), value)))
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Value::Nil => serializer.serialize_unit(),
+            Value::Boolean(b) => serializer.serialize_bool(*b),
+            Value::Integer(i) => serializer.serialize_i128(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Table(table) => table.serialize(serializer)
+        }
+    }
+}
+
+// Tables with only an array part serialize as sequences, anything else as a
+// map.
+impl Serialize for Table {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.hash.is_empty() {
+            let mut seq = serializer.serialize_seq(Some(self.array.len()))?;
+            for value in &self.array {
+                seq.serialize_element(value)?;
+            }
+            seq.end()
+        } else {
+            let mut map = serializer.serialize_map(Some(self.array.len() + self.hash.len()))?;
+            for (key, value) in self.iter() {
+                map.serialize_entry(&key, value)?;
+            }
+            map.end()
+        }
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Key::Boolean(b) => serializer.serialize_bool(*b),
+            Key::Integer(i) => serializer.serialize_i128(*i),
+            Key::Float(f) => serializer.serialize_f64(*f),
+            Key::String(s) => serializer.serialize_str(s)
+        }
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an LSON value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Boolean(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(i128::from(v)))
+    }
+
+    fn visit_i128<E>(self, v: i128) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(Value::Integer(i128::from(v)))
+    }
+
+    fn visit_u128<E>(self, v: u128) -> std::result::Result<Value, E> {
+        Ok(i128::try_from(v).map(Value::Integer).unwrap_or(Value::Float(v as f64)))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Value::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Nil)
+    }
+
+    fn visit_some<D: de::Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Value, D::Error> {
+        Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Value, D::Error> {
+        Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut table = Table::new();
+        while let Some(value) = seq.next_element()? {
+            table.push(value);
+        }
+        Ok(Value::Table(table))
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> std::result::Result<Value, A::Error> {
+        let mut table = Table::new();
+        while let Some(key) = map.next_key::<Value>()? {
+            let type_name = key.type_name();
+            let key = key.into_key()
+                .ok_or_else(|| de::Error::custom(format!("invalid table key of type {}", type_name)))?;
+            table.insert(key, map.next_value()?);
+        }
+        Ok(Value::Table(table))
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> std::result::Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Value {
+        self
+    }
+}
+
+fn visit_integer<'de, V: Visitor<'de>>(v: i128, visitor: V) -> Result<V::Value> {
+    if let Ok(v) = i64::try_from(v) {
+        visitor.visit_i64(v)
+    } else if let Ok(v) = u64::try_from(v) {
+        visitor.visit_u64(v)
+    } else {
+        visitor.visit_i128(v)
+    }
+}
+
+// Deserializing from a `Value` is how references to `local` bindings are
+// read, so it behaves like `de::Deserializer` does for the same source text.
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Nil => visitor.visit_unit(),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visit_integer(i, visitor),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) => visitor.visit_string(s),
+            Value::Table(table) => {
+                if table.hash.is_empty() {
+                    let mut seq = SeqDeserializer::new(table.array.into_iter());
+                    let value = visitor.visit_seq(&mut seq)?;
+                    seq.end()?;
+                    Ok(value)
+                } else {
+                    let mut map = MapDeserializer::new(table.into_entries());
+                    let value = visitor.visit_map(&mut map)?;
+                    map.end()?;
+                    Ok(value)
+                }
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Nil => visitor.visit_none(),
+            value => visitor.visit_some(value)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self {
+            Value::Table(table) => {
+                let mut map = MapDeserializer::new(table.into_entries());
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            },
+            value => value.deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value> {
+        match self {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            Value::Table(table) if table.array.is_empty() && table.hash.len() == 1 => {
+                let (key, value) = table.hash.into_iter().next().unwrap();
+                match key {
+                    Key::String(variant) => visitor.visit_enum(EnumDeserializer { variant, value }),
+                    key => Err(de::Error::invalid_type(de::Unexpected::Other(Value::from(key).type_name()), &"a variant name"))
+                }
+            },
+            _ => Err(de::Error::invalid_type(de::Unexpected::Other("value"), &"a string or a table with a single entry"))
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct identifier
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value: Value
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = Value;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Value)> {
+        let variant = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Value {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self {
+            Value::Nil => Ok(()),
+            value => Err(de::Error::invalid_type(de::Unexpected::Other(value.type_name()), &"unit variant"))
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{de, ser};
+
+    #[test]
+    fn array_and_hash_parts() {
+        let mut table = Table::new();
+        table.insert(Key::Integer(2), Value::Integer(20));
+        table.insert(Key::String("x".to_string()), Value::Boolean(true));
+        assert_eq!(table.len(), 0);
+        table.push(Value::Integer(10));
+        assert_eq!(table.array(), &[Value::Integer(10), Value::Integer(20)]);
+        assert_eq!(table.hash().len(), 1);
+        table.insert(Key::String("x".to_string()), Value::Nil);
+        assert!(table.hash().is_empty());
+        assert_eq!(table.get(&Key::Integer(2)), Some(&Value::Integer(20)));
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(Value::Float(1.0).into_key(), Some(Key::Integer(1)));
+        assert_eq!(Value::Float(1.5).into_key(), Some(Key::Float(1.5)));
+        assert_eq!(Value::Float(f64::NAN).into_key(), None);
+        assert_eq!(Value::Nil.into_key(), None);
+        let table: Value = de::from_str("{ [1.0] = \"a\", [2] = \"b\" }").unwrap();
+        match table {
+            Value::Table(table) => assert_eq!(table.len(), 2),
+            value => panic!("unexpected {:?}", value)
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let text = "{ 1, 2, name = \"a\", nested = { x = 1.5, [true] = false } }";
+        let value: Value = de::from_str(text).unwrap();
+        assert_eq!(de::from_str::<Value>(&ser::to_string(&value).unwrap()).unwrap(), value);
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn source_order() {
+        let text = "{ zebra = 1, apple = { y = 1, b = 2, [10] = 3 }, mango = 3, [\"x y\"] = 4 }";
+        let value: Value = de::from_str(text).unwrap();
+        assert_eq!(ser::to_string(&value).unwrap(), text);
+        let pretty = ser::to_string_pretty(&value).unwrap();
+        assert_eq!(ser::to_string(&de::from_str::<Value>(&pretty).unwrap()).unwrap(), text);
+        assert_eq!(ser::to_string_canonical(&value).unwrap(),
+            "{ apple = { [10] = 3, b = 2, y = 1 }, mango = 3, [\"x y\"] = 4, zebra = 1 }");
     }
 }